@@ -0,0 +1,170 @@
+
+use crate::{alt, ParseError, Reason};
+
+// Implemented for every parser function and closure, so the methods can be used on
+// anything the macros accept, and everything they return can be used in the macros.
+pub trait Parser<I, T> : FnMut(&mut I) -> Result<T, ParseError> + Sized {
+    fn map<U>(mut self, mut f : impl FnMut(T) -> U) -> impl FnMut(&mut I) -> Result<U, ParseError> {
+        move |input| self(input).map(&mut f)
+    }
+
+    fn or(mut self, mut other : impl Parser<I, T>) -> impl FnMut(&mut I) -> Result<T, ParseError>
+        where I : Clone
+    {
+        move |input| alt!(input => self; other)
+    }
+
+    fn then<U>(mut self, mut next : impl Parser<I, U>) -> impl FnMut(&mut I) -> Result<(T, U), ParseError>
+        where I : Clone
+    {
+        move |input| {
+            let mut rp = input.clone();
+            let a = match self(input) {
+                Ok(a) => a,
+                Err(ParseError::Error) => {
+                    std::mem::swap(input, &mut rp);
+                    return Err(ParseError::Error);
+                },
+                Err(e) => return Err(e),
+            };
+            match next(input) {
+                Ok(b) => Ok((a, b)),
+                Err(ParseError::Error) => {
+                    std::mem::swap(input, &mut rp);
+                    Err(ParseError::Error)
+                },
+                Err(e) => Err(e),
+            }
+        }
+    }
+
+    fn many(mut self) -> impl FnMut(&mut I) -> Result<Vec<T>, ParseError>
+        where I : Clone
+    {
+        move |input| {
+            let mut ret = vec![];
+            loop {
+                let mut peek = input.clone();
+                match self(input) {
+                    Ok(x) => ret.push(x),
+                    Err(ParseError::Error) => {
+                        std::mem::swap(input, &mut peek);
+                        return Ok(ret);
+                    },
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+    }
+
+    fn fatal(mut self, name : &'static str) -> impl FnMut(&mut I) -> Result<T, ParseError>
+        where I : Clone
+    {
+        move |input| {
+            let mut rp = input.clone();
+            match self(input) {
+                Err(ParseError::Error) => {
+                    std::mem::swap(input, &mut rp);
+                    Err(ParseError::Fatal(vec![Reason::Rule(name)]))
+                },
+                result => result,
+            }
+        }
+    }
+
+    fn label(mut self, name : &'static str) -> impl FnMut(&mut I) -> Result<T, ParseError> {
+        move |input| match self(input) {
+            Err(ParseError::Fatal(mut reasons)) => {
+                reasons.push(Reason::Rule(name));
+                Err(ParseError::Fatal(reasons))
+            },
+            result => result,
+        }
+    }
+}
+
+impl<I, T, F> Parser<I, T> for F where F : FnMut(&mut I) -> Result<T, ParseError> { }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::*;
+    use std::str::Chars;
+
+    fn number(input : &mut Chars) -> Result<u32, ParseError> {
+        text::digit.many().map(|ds| ds.into_iter().collect::<String>()).map(|s| s.parse().unwrap_or(0))(input)
+    }
+
+    #[test]
+    fn parser_methods_should_compose() {
+        let input = "12+34";
+        let mut input = input.chars();
+
+        let output = number.then(text::literal("+").then(number).map(|(_, b)| b))(&mut input).expect("the parse should be successful");
+
+        assert_eq!(output, (12, 34));
+        assert_eq!(input.as_str(), "");
+    }
+
+    #[test]
+    fn parser_methods_should_mix_with_macros() {
+        let input = "a1b2";
+        let mut input = input.chars();
+
+        let output = parser!(input => {
+            xs <= * text::alpha.then(text::digit).map(|(a, d)| format!("{}{}", a, d));
+            end;
+            select xs
+        }).expect("the parse should be successful");
+
+        assert_eq!(output, ["a1", "b2"]);
+    }
+
+    #[test]
+    fn or_should_try_alternatives() {
+        let input = "y";
+        let mut input = input.chars();
+
+        let output = text::literal("x").or(text::literal("y"))(&mut input).expect("the parse should be successful");
+
+        assert_eq!(output, "y");
+    }
+
+    #[test]
+    fn then_should_reset_input_on_error() {
+        let input = "ab";
+        let mut input = input.chars();
+
+        let output = text::any.then(text::digit)(&mut input);
+
+        assert!( matches!(output, Err(ParseError::Error)) );
+        assert_eq!( input.as_str(), "ab" );
+    }
+
+    #[test]
+    fn then_should_reset_input_when_first_parser_fails() {
+        let input = "ab";
+        let mut input = input.chars();
+
+        let output = text::literal("ax").then(text::any)(&mut input);
+
+        assert!( matches!(output, Err(ParseError::Error)) );
+        assert_eq!( input.as_str(), "ab" );
+    }
+
+    #[test]
+    fn fatal_and_label_should_trace() {
+        let input = "x";
+        let mut input = input.chars();
+
+        let output = text::literal("a").or(text::digit.fatal("digit").label("number").map(|_| "d"))(&mut input);
+
+        if let Err(ParseError::Fatal(reasons)) = output {
+            assert_eq!(reasons, [Reason::Rule("digit"), Reason::Rule("number"), Reason::Alt]);
+            assert_eq!(input.as_str(), "x");
+        }
+        else {
+            panic!();
+        }
+    }
+}
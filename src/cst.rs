@@ -0,0 +1,658 @@
+
+// Every rule defined with `rule!` or `grammar!` becomes a node named after the
+// rule.  Hand written parsers become nodes when wrapped in `node` or `node!`.
+// Text consumed outside of a `token` is a token of kind `TEXT`.
+
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::{ParseError, Reason, Span};
+use crate::input::Input;
+use crate::trace::At;
+
+pub const ERROR : &str = "error";
+pub const TEXT : &str = "text";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub span : Span,
+    pub reasons : Vec<Reason>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GreenToken {
+    kind : &'static str,
+    text : String,
+}
+
+impl GreenToken {
+    pub fn kind(&self) -> &'static str {
+        self.kind
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GreenElement {
+    Node(Rc<GreenNode>),
+    Token(Rc<GreenToken>),
+}
+
+impl GreenElement {
+    pub fn text_len(&self) -> usize {
+        match self {
+            GreenElement::Node(n) => n.text_len(),
+            GreenElement::Token(t) => t.text.len(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GreenNode {
+    kind : &'static str,
+    len : usize,
+    children : Vec<GreenElement>,
+}
+
+impl GreenNode {
+    pub fn new(kind : &'static str, children : Vec<GreenElement>) -> Self {
+        let len = children.iter().map(GreenElement::text_len).sum();
+        GreenNode { kind, len, children }
+    }
+
+    pub fn kind(&self) -> &'static str {
+        self.kind
+    }
+
+    pub fn text_len(&self) -> usize {
+        self.len
+    }
+
+    pub fn children(&self) -> &[GreenElement] {
+        &self.children
+    }
+
+    pub fn text(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl std::fmt::Display for GreenNode {
+    fn fmt(&self, f : &mut std::fmt::Formatter) -> std::fmt::Result {
+        for child in &self.children {
+            match child {
+                GreenElement::Node(n) => write!(f, "{}", n)?,
+                GreenElement::Token(t) => write!(f, "{}", t.text)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Event {
+    Start(&'static str),
+    Token(char),
+    Finish,
+    StartToken(&'static str),
+    FinishToken,
+    Diagnostic(Diagnostic),
+    Node(Rc<GreenNode>),
+}
+
+// Successful rule results by (rule, offset), for incremental reparsing.  `reach`
+// is where the bytes the rule looked at, including lookahead, end; an end past
+// the text means it saw the end of input.  Only entries whose node made it into
+// a tree are reused.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Memo {
+    entries : HashMap<(&'static str, usize), Entry>,
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    len : usize,
+    reach : usize,
+    value : Rc<dyn Any>,
+    diagnostics : Vec<Diagnostic>,
+    node : Option<Rc<GreenNode>>,
+}
+
+impl Memo {
+    // Keeps the entries that looked at nothing in `start..end` of the old text,
+    // moving those after it by the change in length.
+    pub(crate) fn edit(&self, start : usize, end : usize, len : usize) -> Memo {
+        let shift = |at : usize| at + len - (end - start);
+        let entries = self.entries.iter().filter_map(|(&(name, at), entry)| {
+            if entry.reach <= start {
+                Some(((name, at), entry.clone()))
+            }
+            else if at >= end {
+                let mut entry = entry.clone();
+                entry.reach = shift(entry.reach);
+                for d in &mut entry.diagnostics {
+                    d.span = Span { start : shift(d.span.start), end : shift(d.span.end) };
+                }
+                Some(((name, shift(at)), entry))
+            }
+            else {
+                None
+            }
+        }).collect();
+        Memo { entries }
+    }
+
+    pub(crate) fn retain_reusable(&mut self) {
+        self.entries.retain(|_, entry| entry.node.is_some());
+    }
+}
+
+// Every clone remembers how many events it has seen.  Restoring an older clone
+// (which is how the macros backtrack) makes the next push drop the events of the
+// abandoned branch.
+#[derive(Debug)]
+pub struct Cst<I> {
+    inner : I,
+    events : Rc<RefCell<Vec<Event>>>,
+    len : usize,
+    offset : usize,
+    memo : Option<Rc<RefCell<Memo>>>,
+    reach : Rc<Cell<usize>>,
+}
+
+impl<I : Clone> Clone for Cst<I> {
+    fn clone(&self) -> Self {
+        Cst {
+            inner : self.inner.clone(),
+            events : Rc::clone(&self.events),
+            len : self.len,
+            offset : self.offset,
+            memo : self.memo.clone(),
+            reach : Rc::clone(&self.reach),
+        }
+    }
+}
+
+impl<I : Iterator<Item = char>> Cst<I> {
+    pub fn new(inner : I) -> Self {
+        Cst { inner, events : Rc::new(RefCell::new(vec![])), len : 0, offset : 0, memo : None, reach : Rc::new(Cell::new(0)) }
+    }
+
+    pub(crate) fn with_memo(inner : I, memo : Rc<RefCell<Memo>>) -> Self {
+        Cst { memo : Some(memo), ..Cst::new(inner) }
+    }
+
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    fn push(&mut self, event : Event) {
+        let mut events = self.events.borrow_mut();
+        events.truncate(self.len);
+        events.push(event);
+        self.len += 1;
+    }
+
+    pub fn start(&mut self, kind : &'static str) {
+        self.push(Event::Start(kind));
+    }
+
+    pub fn finish(&mut self) {
+        self.push(Event::Finish);
+    }
+
+    pub fn start_token(&mut self, kind : &'static str) {
+        self.push(Event::StartToken(kind));
+    }
+
+    pub fn finish_token(&mut self) {
+        self.push(Event::FinishToken);
+    }
+
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        self.events.borrow()[..self.len].iter().filter_map(|event| match event {
+            Event::Diagnostic(d) => Some(d.clone()),
+            _ => None,
+        }).collect()
+    }
+
+    pub fn into_tree(mut self, root : &'static str) -> GreenNode {
+        while self.next().is_some() { }
+        self.tree(root)
+    }
+
+    pub(crate) fn tree(&self, root : &'static str) -> GreenNode {
+        let events = self.events.borrow();
+        let mut stack : Vec<(&'static str, Vec<GreenElement>, usize)> = vec![(root, vec![], 0)];
+        let mut tokens = vec![TEXT];
+        let mut text = String::new();
+        let mut offset = 0;
+
+        fn flush(kind : &'static str, text : &mut String, children : &mut Vec<GreenElement>) {
+            if !text.is_empty() {
+                children.push(GreenElement::Token(Rc::new(GreenToken { kind, text : std::mem::take(text) })));
+            }
+        }
+
+        for event in &events[..self.len] {
+            let kind = *tokens.last().expect("TEXT is never popped");
+            match event {
+                Event::Token(c) => {
+                    text.push(*c);
+                    offset += c.len_utf8();
+                },
+                Event::Start(node) => {
+                    flush(kind, &mut text, &mut stack.last_mut().expect("root is never popped").1);
+                    stack.push((node, vec![], offset));
+                },
+                Event::Finish if stack.len() > 1 => {
+                    let (node, mut children, start) = stack.pop().expect("stack has more than the root");
+                    flush(kind, &mut text, &mut children);
+                    let node = Rc::new(GreenNode::new(node, children));
+                    if let Some(memo) = &self.memo {
+                        if let Some(entry) = memo.borrow_mut().entries.get_mut(&(node.kind(), start)) {
+                            if entry.len == node.text_len() && entry.node.is_none() {
+                                entry.node = Some(Rc::clone(&node));
+                            }
+                        }
+                    }
+                    stack.last_mut().expect("root is never popped").1.push(GreenElement::Node(node));
+                },
+                Event::Node(node) => {
+                    let children = &mut stack.last_mut().expect("root is never popped").1;
+                    flush(kind, &mut text, children);
+                    children.push(GreenElement::Node(Rc::clone(node)));
+                    offset += node.text_len();
+                },
+                Event::StartToken(token) => {
+                    flush(kind, &mut text, &mut stack.last_mut().expect("root is never popped").1);
+                    tokens.push(token);
+                },
+                Event::FinishToken if tokens.len() > 1 => {
+                    flush(kind, &mut text, &mut stack.last_mut().expect("root is never popped").1);
+                    tokens.pop();
+                },
+                Event::Finish | Event::FinishToken | Event::Diagnostic(_) => { },
+            }
+        }
+
+        let kind = *tokens.last().expect("TEXT is never popped");
+        while let Some((node, mut children, _)) = stack.pop() {
+            flush(kind, &mut text, &mut children);
+            let node = GreenNode::new(node, children);
+            match stack.last_mut() {
+                Some((_, parent, _)) => parent.push(GreenElement::Node(Rc::new(node))),
+                None => return node,
+            }
+        }
+        unreachable!("the root node is always returned")
+    }
+}
+
+impl<I : Iterator<Item = char>> Iterator for Cst<I> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.inner.next();
+        let examined = self.offset + c.map_or(1, char::len_utf8);
+        if self.reach.get() < examined {
+            self.reach.set(examined);
+        }
+        let c = c?;
+        self.offset += c.len_utf8();
+        self.push(Event::Token(c));
+        Some(c)
+    }
+}
+
+impl<I : Iterator<Item = char> + Clone> Input for Cst<I> {
+    fn at(&self) -> At {
+        At::Offset(self.offset)
+    }
+
+    fn rule<T>(&mut self, name : &'static str, parser : impl FnOnce(&mut Self) -> Result<T, ParseError>) -> Result<T, ParseError> {
+        let mut rp = self.clone();
+        self.start(name);
+        match parser(self) {
+            Ok(x) => {
+                self.finish();
+                Ok(x)
+            },
+            Err(ParseError::Error) => {
+                std::mem::swap(self, &mut rp);
+                Err(ParseError::Error)
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    fn cached<T : Clone + 'static>(&mut self, name : &'static str, parser : impl FnOnce(&mut Self) -> Result<T, ParseError>) -> Result<T, ParseError> {
+        let Some(memo) = self.memo.clone() else { return self.rule(name, parser); };
+        let start = self.offset;
+
+        let hit = memo.borrow().entries.get(&(name, start)).and_then(|entry| {
+            let value = entry.value.downcast_ref::<T>()?.clone();
+            Some((entry.node.clone()?, value, entry.len, entry.reach, entry.diagnostics.clone()))
+        });
+        if let Some((node, value, len, reach, diagnostics)) = hit {
+            let mut skipped = 0;
+            while skipped < len {
+                match self.inner.next() {
+                    Some(c) => skipped += c.len_utf8(),
+                    None => break,
+                }
+            }
+            self.offset += skipped;
+            self.reach.set(self.reach.get().max(reach));
+            self.push(Event::Node(node));
+            for d in diagnostics {
+                self.push(Event::Diagnostic(d));
+            }
+            return Ok(value);
+        }
+
+        let first = self.len;
+        let outer = self.reach.replace(start);
+        let result = self.rule(name, parser);
+        let reach = self.reach.get();
+        self.reach.set(outer.max(reach));
+
+        if let Ok(value) = &result {
+            let diagnostics = self.events.borrow()[first..self.len].iter().filter_map(|event| match event {
+                Event::Diagnostic(d) => Some(d.clone()),
+                _ => None,
+            }).collect();
+            let entry = Entry { len : self.offset - start, reach, value : Rc::new(value.clone()), diagnostics, node : None };
+            memo.borrow_mut().entries.insert((name, start), entry);
+        }
+        result
+    }
+}
+
+pub fn node<I, T>(kind : &'static str, mut parser : impl FnMut(&mut Cst<I>) -> Result<T, ParseError>) -> impl FnMut(&mut Cst<I>) -> Result<T, ParseError>
+    where I : Iterator<Item = char> + Clone
+{
+    move |input| input.rule(kind, &mut parser)
+}
+
+// Everything `parser` consumes becomes a single token of the given kind.
+pub fn token<I, T>(kind : &'static str, mut parser : impl FnMut(&mut Cst<I>) -> Result<T, ParseError>) -> impl FnMut(&mut Cst<I>) -> Result<T, ParseError>
+    where I : Iterator<Item = char> + Clone
+{
+    move |input| {
+        let mut rp = input.clone();
+        input.start_token(kind);
+        match parser(input) {
+            Ok(x) => {
+                input.finish_token();
+                Ok(x)
+            },
+            Err(ParseError::Error) => {
+                std::mem::swap(input, &mut rp);
+                Err(ParseError::Error)
+            },
+            Err(e) => Err(e),
+        }
+    }
+}
+
+// A failing `parser` does not fail the parse.  Instead everything up to and
+// including the next `sync` match is wrapped in an `ERROR` node together with a
+// diagnostic, and `Ok(None)` is returned.  Only an exhausted input fails with
+// `ParseError::Error`, so `* recover(..)` consumes the whole input.
+pub fn recover<I, T, S>(mut parser : impl FnMut(&mut Cst<I>) -> Result<T, ParseError>, mut sync : impl FnMut(&mut Cst<I>) -> Result<S, ParseError>) -> impl FnMut(&mut Cst<I>) -> Result<Option<T>, ParseError>
+    where I : Iterator<Item = char> + Clone
+{
+    move |input| {
+        if input.clone().next().is_none() {
+            return Err(ParseError::Error);
+        }
+
+        let mut rp = input.clone();
+        let reasons = match parser(input) {
+            Ok(x) => return Ok(Some(x)),
+            Err(ParseError::Error) => vec![],
+            Err(ParseError::Fatal(reasons)) => reasons,
+            Err(e @ ParseError::Incomplete { .. }) => return Err(e),
+        };
+        std::mem::swap(input, &mut rp);
+
+        let start = input.offset();
+        input.start(ERROR);
+        loop {
+            let mut rp = input.clone();
+            match sync(input) {
+                Ok(_) if input.offset() > start => break,
+                _ => std::mem::swap(input, &mut rp),
+            }
+            if input.next().is_none() {
+                break;
+            }
+        }
+        input.finish();
+
+        let span = Span { start, end : input.offset() };
+        input.push(Event::Diagnostic(Diagnostic { span, reasons }));
+        Ok(None)
+    }
+}
+
+#[macro_export]
+macro_rules! node {
+    ($input:ident => $kind:ident { $($rest:tt)* }) => {
+        $crate::input::Input::cached($input, stringify!($kind), |input| parser!(input as $kind => { $($rest)* }))
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::*;
+    use std::str::Chars;
+
+    fn kinds(node : &GreenNode) -> Vec<&'static str> {
+        let mut ret = vec![node.kind()];
+        for child in node.children() {
+            if let GreenElement::Node(n) = child {
+                ret.extend(kinds(n));
+            }
+        }
+        ret
+    }
+
+    fn number(input : &mut Cst<Chars>) -> Result<u32, ParseError> {
+        node!(input => number {
+            ds <= * text::digit;
+            where !ds.is_empty();
+            select ds.into_iter().collect::<String>().parse().unwrap()
+        })
+    }
+
+    fn sum(input : &mut Cst<Chars>) -> Result<u32, ParseError> {
+        node!(input => sum {
+            a <= number;
+            _w <= text::ws0;
+            _p <= text::literal("+");
+            _w <= text::ws0;
+            b <= number;
+            select a + b
+        })
+    }
+
+    fn expr(input : &mut Cst<Chars>) -> Result<u32, ParseError> {
+        node("expr", |input| alt!(input => sum; number))(input)
+    }
+
+    #[test]
+    fn cst_should_reproduce_source() {
+        let source = "12 +  3 // trailing";
+        let mut input = Cst::new(source.chars());
+
+        let output = expr(&mut input).expect("the parse should be successful");
+        let tree = input.into_tree("root");
+
+        assert_eq!(output, 15);
+        assert_eq!(tree.text(), source);
+        assert_eq!(tree.text_len(), source.len());
+        assert_eq!(kinds(&tree), ["root", "expr", "sum", "number", "number"]);
+    }
+
+    #[test]
+    fn cst_should_discard_abandoned_branches() {
+        let source = "12";
+        let mut input = Cst::new(source.chars());
+
+        let output = expr(&mut input).expect("the parse should be successful");
+        let tree = input.into_tree("root");
+
+        assert_eq!(output, 12);
+        assert_eq!(tree.text(), source);
+        assert_eq!(kinds(&tree), ["root", "expr", "number"]);
+    }
+
+    fn tokens(node : &GreenNode) -> Vec<(&'static str, String)> {
+        let mut ret = vec![];
+        for child in node.children() {
+            match child {
+                GreenElement::Node(n) => ret.extend(tokens(n)),
+                GreenElement::Token(t) => ret.push((t.kind(), t.text().to_string())),
+            }
+        }
+        ret
+    }
+
+    mod words {
+        use crate::*;
+
+        grammar! {
+            pub item char;
+
+            rule words -> Vec<String> = {
+                ws <= * word;
+                select ws
+            };
+
+            rule word -> String = {
+                _w <= text::ws0;
+                w <= text::ident;
+                select w
+            };
+        }
+    }
+
+    #[test]
+    fn rules_should_become_nodes_named_after_them() {
+        let source = "a bc";
+        let mut input = Cst::new(source.chars());
+
+        let output = words::words(&mut input).expect("the parse should be successful");
+        let tree = input.into_tree("root");
+
+        assert_eq!(output, ["a", "bc"]);
+        assert_eq!(tree.text(), source);
+        assert_eq!(kinds(&tree), ["root", "words", "word", "word"]);
+    }
+
+    #[test]
+    fn cst_should_give_tokens_kinds() {
+        let source = "let x";
+        let mut input = Cst::new(source.chars());
+
+        parser!(input => {
+            _l <= token("keyword", text::literal("let"));
+            _w <= text::ws1;
+            _i <= token("ident", text::ident);
+            select ()
+        }).expect("the parse should be successful");
+
+        let tree = input.into_tree("root");
+        assert_eq!(tokens(&tree), [("keyword", "let".to_string()), (TEXT, " ".to_string()), ("ident", "x".to_string())]);
+    }
+
+    #[test]
+    fn cst_should_discard_failed_nodes() {
+        let source = "x";
+        let mut input = Cst::new(source.chars());
+
+        let output = expr(&mut input);
+        let tree = input.into_tree("root");
+
+        assert!( matches!(output, Err(ParseError::Error)) );
+        assert_eq!(tree.text(), source);
+        assert_eq!(kinds(&tree), ["root"]);
+    }
+
+    fn statement(input : &mut Cst<Chars>) -> Result<(String, u32), ParseError> {
+        node!(input => statement {
+            _w <= text::ws0;
+            _l <= text::literal("let");
+            _w <= text::ws1;
+            name <= text::ident;
+            _w <= text::ws0;
+            _e <= ! text::literal("=");
+            _w <= text::ws0;
+            value <= ! number;
+            _s <= ! text::literal(";");
+            select (name, value)
+        })
+    }
+
+    #[test]
+    fn recover_should_produce_error_nodes_and_continue() {
+        let source = "let a = 1;\nlet b = ;\nlet c = 3;";
+        let mut input = Cst::new(source.chars());
+
+        let output = parser!(input => {
+            ss <= * recover(statement, text::literal(";"));
+            end;
+            select ss
+        }).expect("the parse should be successful");
+
+        assert_eq!(output, [Some(("a".to_string(), 1)), None, Some(("c".to_string(), 3))]);
+
+        let diagnostics = input.diagnostics();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].span, Span { start : 10, end : 20 });
+        assert_eq!(diagnostics[0].reasons, [Reason::Rule("value")]);
+
+        let tree = input.into_tree("root");
+        assert_eq!(tree.text(), source);
+        assert_eq!(kinds(&tree), ["root", "statement", "number", ERROR, "statement", "number"]);
+    }
+
+    #[test]
+    fn recover_should_skip_to_end_without_sync() {
+        let source = "let a = 1; junk";
+        let mut input = Cst::new(source.chars());
+
+        let output = parser!(input => {
+            ss <= * recover(statement, text::literal(";"));
+            select ss
+        }).expect("the parse should be successful");
+
+        assert_eq!(output.len(), 2);
+        assert_eq!(input.diagnostics()[0].span, Span { start : 10, end : 15 });
+        assert_eq!(input.into_tree("root").text(), source);
+    }
+
+    #[test]
+    fn recover_should_discard_diagnostics_on_backtrack() {
+        let source = "x";
+        let mut input = Cst::new(source.chars());
+
+        let output = parser!(input => {
+            _s <= recover(statement, text::literal(";"));
+            where false;
+            select ()
+        });
+
+        assert!( matches!(output, Err(ParseError::Error)) );
+        assert!( input.diagnostics().is_empty() );
+        assert_eq!( kinds(&input.into_tree("root")), ["root"] );
+    }
+}
@@ -0,0 +1,236 @@
+
+use std::ops::Deref;
+use std::rc::Rc;
+
+use crate::{ParseError, Reason};
+use crate::recursive::Recursive;
+
+// Dereferences to `dyn Fn`, so a `DynParser` can be called like any other parser
+// and used directly in `parser!` and `alt!`.  Anything that takes a parser by value,
+// like the `combinator::Parser` methods or `rule!` parameters, takes `as_fn()`.
+pub struct DynParser<'a, I, T> {
+    parser : Recursive<'a, I, T>,
+}
+
+impl<'a, I, T> Clone for DynParser<'a, I, T> {
+    fn clone(&self) -> Self {
+        DynParser { parser : Rc::clone(&self.parser) }
+    }
+}
+
+impl<'a, I, T> Deref for DynParser<'a, I, T> {
+    type Target = dyn Fn(&mut I) -> Result<T, ParseError> + 'a;
+
+    fn deref(&self) -> &Self::Target {
+        &*self.parser
+    }
+}
+
+impl<'a, I, T> From<Recursive<'a, I, T>> for DynParser<'a, I, T> {
+    fn from(parser : Recursive<'a, I, T>) -> Self {
+        DynParser { parser }
+    }
+}
+
+impl<'a, I : Clone + 'a, T : 'a> DynParser<'a, I, T> {
+    pub fn new(parser : impl Fn(&mut I) -> Result<T, ParseError> + 'a) -> Self {
+        DynParser { parser : Rc::new(parser) }
+    }
+
+    pub fn as_fn(&self) -> impl Fn(&mut I) -> Result<T, ParseError> + '_ {
+        move |input| (self.parser)(input)
+    }
+
+    pub fn sequence(parsers : Vec<DynParser<'a, I, T>>) -> DynParser<'a, I, Vec<T>> {
+        DynParser::new(move |input : &mut I| {
+            let mut rp = input.clone();
+            let mut ret = Vec::with_capacity(parsers.len());
+            for parser in &parsers {
+                match parser(input) {
+                    Ok(x) => ret.push(x),
+                    Err(ParseError::Error) => {
+                        std::mem::swap(input, &mut rp);
+                        return Err(ParseError::Error);
+                    },
+                    Err(e) => return Err(e),
+                }
+            }
+            Ok(ret)
+        })
+    }
+
+    pub fn choice(parsers : Vec<DynParser<'a, I, T>>) -> DynParser<'a, I, T> {
+        DynParser::new(move |input : &mut I| {
+            for parser in &parsers {
+                let mut rp = input.clone();
+                match parser(input) {
+                    Ok(x) => return Ok(x),
+                    Err(ParseError::Error) => { std::mem::swap(input, &mut rp); },
+                    Err(ParseError::Fatal(mut reasons)) => {
+                        reasons.push(Reason::Alt);
+                        return Err(ParseError::Fatal(reasons));
+                    },
+                    Err(e @ ParseError::Incomplete { .. }) => return Err(e),
+                }
+            }
+            Err(ParseError::Error)
+        })
+    }
+
+    pub fn optional(self) -> DynParser<'a, I, Option<T>> {
+        DynParser::new(move |input : &mut I| {
+            let mut rp = input.clone();
+            match self(input) {
+                Ok(x) => Ok(Some(x)),
+                Err(ParseError::Error) => {
+                    std::mem::swap(input, &mut rp);
+                    Ok(None)
+                },
+                Err(e) => Err(e),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::*;
+    use crate::recursive::recursive;
+    use crate::combinator::Parser;
+    use std::str::Chars;
+
+    fn operators<'a>(table : &'a [(&'a str, i64)]) -> DynParser<'a, Chars<'a>, i64> {
+        DynParser::choice(table.iter().map(|(op, prec)| DynParser::new(move |input : &mut Chars<'a>| text::literal(op)(input).map(|_| *prec))).collect())
+    }
+
+    #[test]
+    fn dyn_parser_should_compose_at_runtime() {
+        let table = vec![("**", 3), ("*", 2), ("+", 1)];
+        let op = operators(&table);
+        let number = DynParser::new(number::integer::<i64>);
+
+        let input = "1**2+3*4";
+        let mut input = input.chars();
+
+        let term = DynParser::sequence(vec![op, number.clone()]);
+        let output = parser!(input => {
+            first <= number;
+            rest <= * term;
+            end;
+            select (first, rest)
+        }).expect("the parse should be successful");
+
+        assert_eq!(output, (1, vec![vec![3, 2], vec![1, 3], vec![2, 4]]));
+    }
+
+    #[test]
+    fn dyn_parser_should_be_usable_in_alt() {
+        let y = DynParser::new(text::literal("y"));
+        let z = DynParser::new(text::literal("z"));
+
+        let input = "z";
+        let mut input = input.chars();
+
+        let output = alt!(input => y; z).expect("the parse should be successful");
+
+        assert_eq!(output, "z");
+    }
+
+    #[test]
+    fn dyn_sequence_should_reset_input_on_error() {
+        let p = DynParser::sequence(vec![DynParser::new(text::any), DynParser::new(text::digit)]);
+
+        let input = "ab";
+        let mut input = input.chars();
+
+        let output = p(&mut input);
+
+        assert!( matches!(output, Err(ParseError::Error)) );
+        assert_eq!( input.as_str(), "ab" );
+    }
+
+    #[test]
+    fn dyn_optional_should_parse() {
+        let sign = DynParser::new(text::one_of("+-")).optional();
+        let p = DynParser::sequence(vec![sign.clone(), sign]);
+
+        let input = "-42";
+        let mut input = input.chars();
+
+        let output = p(&mut input).expect("the parse should be successful");
+
+        assert_eq!(output, [Some('-'), None]);
+        assert_eq!(input.as_str(), "42");
+    }
+
+    #[test]
+    fn dyn_parser_should_work_with_parser_methods() {
+        let digit = DynParser::new(text::digit);
+
+        let input = "a42x";
+        let mut input = input.chars();
+
+        let output = text::alpha.or(digit.as_fn()).then(digit.as_fn().many())(&mut input).expect("the parse should be successful");
+
+        assert_eq!(output, ('a', vec!['4', '2']));
+        assert_eq!(input.as_str(), "x");
+    }
+
+    rule!(pair<T>(p => T) : char => (T, T) = {
+        a <= p;
+        b <= p;
+        select (a, b)
+    });
+
+    #[test]
+    fn dyn_parser_should_work_as_rule_parameter() {
+        let digit = DynParser::new(text::digit);
+
+        let input = "42";
+        let mut input = input.chars();
+
+        let output = pair(digit.as_fn())(&mut input).expect("the parse should be successful");
+
+        assert_eq!(output, ('4', '2'));
+    }
+
+    #[test]
+    fn dyn_parser_should_trace_fatal_errors() {
+        let b = DynParser::new(text::literal("b"));
+        let mut p = text::literal("a").or(b.as_fn().fatal("b").label("b_rule"));
+
+        let input = "c";
+        let mut input = input.chars();
+
+        let output = p(&mut input);
+
+        if let Err(ParseError::Fatal(reasons)) = output {
+            assert_eq!(reasons, [Reason::Rule("b"), Reason::Rule("b_rule"), Reason::Alt]);
+            assert_eq!(input.as_str(), "c");
+        }
+        else {
+            panic!();
+        }
+    }
+
+    fn nested<'a>() -> DynParser<'a, Chars<'a>, usize> {
+        recursive(|nested| move |input : &mut Chars<'a>| {
+            alt!(input => |input : &mut Chars<'a>| parser!(input => {
+                _o <= text::literal("[");
+                n <= nested;
+                _c <= ! text::literal("]");
+                select n + 1
+            }); |_ : &mut Chars<'a>| Ok(0))
+        }).into()
+    }
+
+    #[test]
+    fn dyn_parser_should_wrap_recursive_parsers() {
+        let nested = nested();
+
+        let output = nested(&mut "[[[]]]".chars()).expect("the parse should be successful");
+
+        assert_eq!(output, 3);
+    }
+}
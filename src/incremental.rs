@@ -0,0 +1,297 @@
+
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::str::Chars;
+
+use crate::{ParseError, Span};
+use crate::cst::{Cst, Diagnostic, GreenNode, Memo};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Edit {
+    pub span : Span,
+    pub text : String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EditError {
+    OutOfRange,
+    Reversed,
+    NotCharBoundary,
+}
+
+impl std::fmt::Display for EditError {
+    fn fmt(&self, f : &mut std::fmt::Formatter) -> std::fmt::Result {
+        use EditError::*;
+        match self {
+            OutOfRange => write!(f, "OutOfRange: edit ends past the end of the text"),
+            Reversed => write!(f, "Reversed: edit starts after it ends"),
+            NotCharBoundary => write!(f, "NotCharBoundary: edit splits a character"),
+        }
+    }
+}
+
+impl std::error::Error for EditError {}
+
+// Reparsing runs the whole grammar again over the edited text, but every rule
+// defined with `rule!`, `grammar!` or `node!` that succeeded at an offset whose
+// text (and lookahead) the edit left alone returns its previous value and subtree
+// without running.  Only successful results are kept, and only rules whose output
+// is `Clone + 'static`.
+#[derive(Debug, Clone, Copy)]
+pub struct Incremental<T> {
+    pub root : &'static str,
+    pub parse : fn(&mut Cst<Chars>) -> Result<T, ParseError>,
+}
+
+#[derive(Debug)]
+pub struct Parsed<T> {
+    pub tree : GreenNode,
+    pub diagnostics : Vec<Diagnostic>,
+    pub result : Result<T, ParseError>,
+    text : String,
+    memo : Memo,
+}
+
+impl<T> Parsed<T> {
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+}
+
+impl<T> Incremental<T> {
+    pub fn parse(&self, text : &str) -> Parsed<T> {
+        self.parse_with(text.to_string(), Memo::default())
+    }
+
+    pub fn reparse(&self, old : &Parsed<T>, edit : &Edit) -> Result<Parsed<T>, EditError> {
+        let Span { start, end } = edit.span;
+        if start > end {
+            return Err(EditError::Reversed);
+        }
+        if end > old.text.len() {
+            return Err(EditError::OutOfRange);
+        }
+        if !old.text.is_char_boundary(start) || !old.text.is_char_boundary(end) {
+            return Err(EditError::NotCharBoundary);
+        }
+
+        let mut text = String::with_capacity(old.text.len() + edit.text.len() - (end - start));
+        text.push_str(&old.text[..start]);
+        text.push_str(&edit.text);
+        text.push_str(&old.text[end..]);
+
+        Ok(self.parse_with(text, old.memo.edit(start, end, edit.text.len())))
+    }
+
+    fn parse_with(&self, text : String, memo : Memo) -> Parsed<T> {
+        let memo = Rc::new(RefCell::new(memo));
+        let mut input = Cst::with_memo(text.chars(), Rc::clone(&memo));
+        let result = (self.parse)(&mut input);
+        let diagnostics = input.diagnostics();
+        let tree = input.into_tree(self.root);
+
+        let mut memo = memo.take();
+        memo.retain_reusable();
+        Parsed { tree, diagnostics, result, text, memo }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::*;
+    use crate::cst::{recover, Cst, GreenElement};
+    use std::cell::Cell;
+
+    thread_local! {
+        static NUMBERS : Cell<usize> = const { Cell::new(0) };
+    }
+
+    fn number(input : &mut Cst<Chars>) -> Result<u32, ParseError> {
+        node!(input => number {
+            ds <= * text::digit;
+            where !ds.is_empty();
+            select {
+                NUMBERS.with(|n| n.set(n.get() + 1));
+                ds.into_iter().collect::<String>().parse().unwrap()
+            }
+        })
+    }
+
+    fn list(input : &mut Cst<Chars>) -> Result<Vec<u32>, ParseError> {
+        node!(input => list {
+            _o <= text::literal("[");
+            ns <= * number_item;
+            _c <= ! text::literal("]");
+            select ns
+        })
+    }
+
+    fn number_item(input : &mut Cst<Chars>) -> Result<u32, ParseError> {
+        parser!(input => {
+            _w <= text::ws0;
+            n <= number;
+            _w <= text::ws0;
+            select n
+        })
+    }
+
+    fn file(input : &mut Cst<Chars>) -> Result<Vec<Option<Vec<u32>>>, ParseError> {
+        parser!(input => {
+            ls <= * recover(list, text::literal("\n"));
+            select ls
+        })
+    }
+
+    fn grammar() -> Incremental<Vec<Option<Vec<u32>>>> {
+        Incremental { root : "file", parse : file }
+    }
+
+    fn lists(tree : &GreenNode) -> Vec<Rc<GreenNode>> {
+        tree.children().iter().filter_map(|c| match c {
+            GreenElement::Node(n) => Some(Rc::clone(n)),
+            _ => None,
+        }).collect()
+    }
+
+    #[test]
+    fn reparse_should_match_full_parse() {
+        let grammar = grammar();
+        let old = grammar.parse("[1 22 3]\n[4]");
+
+        let edit = Edit { span : Span { start : 4, end : 5 }, text : "789".into() };
+        let output = grammar.reparse(&old, &edit).expect("the edit should be valid");
+
+        assert_eq!(output.text(), "[1 2789 3]\n[4]");
+        assert_eq!(output.tree, grammar.parse("[1 2789 3]\n[4]").tree);
+        assert_eq!(output.result.expect("the parse should be successful"), [Some(vec![1, 2789, 3]), None, Some(vec![4])]);
+    }
+
+    #[test]
+    fn reparse_should_reuse_unaffected_subtrees() {
+        let grammar = grammar();
+        let old = grammar.parse("[1 22 3]\n[4]");
+
+        let edit = Edit { span : Span { start : 4, end : 5 }, text : "0".into() };
+        let output = grammar.reparse(&old, &edit).expect("the edit should be valid");
+
+        let before = lists(&old.tree);
+        let after = lists(&output.tree);
+        assert!( !Rc::ptr_eq(&before[0], &after[0]) );
+        assert!( Rc::ptr_eq(&before[2], &after[2]) );
+    }
+
+    #[test]
+    fn reparse_should_grow_to_enclosing_rule() {
+        let grammar = grammar();
+        let old = grammar.parse("[1 22 3]\n[4]");
+
+        let edit = Edit { span : Span { start : 4, end : 5 }, text : " ".into() };
+        let output = grammar.reparse(&old, &edit).expect("the edit should be valid");
+
+        assert_eq!(output.tree, grammar.parse("[1 2  3]\n[4]").tree);
+        assert!( Rc::ptr_eq(&lists(&old.tree)[2], &lists(&output.tree)[2]) );
+    }
+
+    #[test]
+    fn reparse_should_fall_back_to_full_parse() {
+        let grammar = grammar();
+        let old = grammar.parse("[1 22 3]\n[4]");
+
+        let edit = Edit { span : Span { start : 4, end : 5 }, text : "]x[".into() };
+        let output = grammar.reparse(&old, &edit).expect("the edit should be valid");
+        let expected = grammar.parse("[1 2]x[ 3]\n[4]");
+
+        assert_eq!(output.tree, expected.tree);
+        assert_eq!(output.diagnostics, expected.diagnostics);
+    }
+
+    #[test]
+    fn parse_should_return_diagnostics() {
+        let grammar = grammar();
+        let output = grammar.parse("[1 x]\n[4]");
+
+        assert!( output.result.is_ok() );
+        assert_eq!(output.diagnostics.len(), 1);
+        assert_eq!(output.diagnostics[0].span, Span { start : 0, end : 6 });
+    }
+
+    #[test]
+    fn reparse_should_shift_later_diagnostics() {
+        let grammar = grammar();
+        let old = grammar.parse("[1 22]\n[x]");
+
+        let edit = Edit { span : Span { start : 3, end : 4 }, text : "333".into() };
+        let output = grammar.reparse(&old, &edit).expect("the edit should be valid");
+        let expected = grammar.parse("[1 3332]\n[x]");
+
+        assert_eq!(output.tree, expected.tree);
+        assert_eq!(output.diagnostics, expected.diagnostics);
+    }
+
+    #[test]
+    fn reparse_should_reject_invalid_edits() {
+        let grammar = grammar();
+        let old = grammar.parse("[1 é]");
+
+        let edit = |start, end| Edit { span : Span { start, end }, text : "2".into() };
+
+        assert_eq!(grammar.reparse(&old, &edit(2, 9)).err(), Some(EditError::OutOfRange));
+        assert_eq!(grammar.reparse(&old, &edit(3, 2)).err(), Some(EditError::Reversed));
+        assert_eq!(grammar.reparse(&old, &edit(4, 4)).err(), Some(EditError::NotCharBoundary));
+    }
+
+    #[test]
+    fn reparse_should_reuse_rule_results() {
+        let grammar = grammar();
+        let old = grammar.parse("[1 22 3]\n[4 5]");
+
+        NUMBERS.with(|n| n.set(0));
+        let edit = Edit { span : Span { start : 4, end : 5 }, text : "0".into() };
+        let output = grammar.reparse(&old, &edit).expect("the edit should be valid");
+
+        assert_eq!(output.result.expect("the parse should be successful"), [Some(vec![1, 20, 3]), None, Some(vec![4, 5])]);
+        assert_eq!(NUMBERS.with(Cell::get), 1);
+    }
+
+    mod words {
+        use crate::*;
+
+        grammar! {
+            pub item char;
+
+            rule words -> Vec<String> = {
+                ws <= * word;
+                _w <= text::ws0;
+                select ws
+            };
+
+            rule word -> String = {
+                _w <= text::ws0;
+                w <= text::ident;
+                select w
+            };
+        }
+    }
+
+    #[test]
+    fn reparse_should_reuse_grammar_rules() {
+        let grammar = Incremental { root : "file", parse : |input| words::words(input) };
+        let old = grammar.parse("ab cd ef");
+
+        let edit = Edit { span : Span { start : 3, end : 4 }, text : "x".into() };
+        let output = grammar.reparse(&old, &edit).expect("the edit should be valid");
+
+        assert_eq!(output.result.as_ref().expect("the parse should be successful"), &["ab", "xd", "ef"]);
+        assert_eq!(output.tree, grammar.parse("ab xd ef").tree);
+
+        let words = |parsed : &Parsed<Vec<String>>| match &parsed.tree.children()[0] {
+            GreenElement::Node(words) => lists(words),
+            _ => panic!(),
+        };
+        let (before, after) = (words(&old), words(&output));
+        assert!( Rc::ptr_eq(&before[0], &after[0]) );
+        assert!( !Rc::ptr_eq(&before[1], &after[1]) );
+        assert!( Rc::ptr_eq(&before[2], &after[2]) );
+    }
+}
@@ -0,0 +1,83 @@
+
+use std::marker::PhantomData;
+use std::str::{Bytes, CharIndices, Chars};
+
+use crate::ParseError;
+use crate::trace::At;
+
+// Hooks that the functions generated by `pat!` and `rule!` call on their input.
+// Every method has a default that does nothing extra, so an iterator opts in with
+// an empty `impl Input for .. {}`; the inputs of this crate override what they
+// support.
+pub trait Input : Iterator {
+    // Where the input currently is, for the trace.  Must not consume or buffer
+    // anything, so tracing never changes what a streaming input reads.
+    fn at(&self) -> At {
+        At::Unknown
+    }
+
+    // Runs the rule called `name`.  `Cst` wraps what it consumes in a node of
+    // that kind.
+    fn rule<T>(&mut self, name : &'static str, parser : impl FnOnce(&mut Self) -> Result<T, ParseError>) -> Result<T, ParseError>
+        where Self : Sized
+    {
+        let _ = name;
+        parser(self)
+    }
+
+    // Like `rule`, but `Cst` also keeps the result for incremental reparsing.
+    fn cached<T : Clone + 'static>(&mut self, name : &'static str, parser : impl FnOnce(&mut Self) -> Result<T, ParseError>) -> Result<T, ParseError>
+        where Self : Sized
+    {
+        self.rule(name, parser)
+    }
+}
+
+// `(&&Output::<T>(PhantomData)).run(..)` picks `Input::cached` when the rule's
+// output can be kept and `Input::rule` otherwise.
+pub struct Output<T>(pub PhantomData<T>);
+
+pub trait Cached<T> {
+    fn run<I : Input>(&self, input : &mut I, name : &'static str, parser : impl FnOnce(&mut I) -> Result<T, ParseError>) -> Result<T, ParseError>;
+}
+
+pub trait Uncached<T> {
+    fn run<I : Input>(&self, input : &mut I, name : &'static str, parser : impl FnOnce(&mut I) -> Result<T, ParseError>) -> Result<T, ParseError>;
+}
+
+impl<T : Clone + 'static> Cached<T> for &Output<T> {
+    fn run<I : Input>(&self, input : &mut I, name : &'static str, parser : impl FnOnce(&mut I) -> Result<T, ParseError>) -> Result<T, ParseError> {
+        input.cached(name, parser)
+    }
+}
+
+impl<T> Uncached<T> for Output<T> {
+    fn run<I : Input>(&self, input : &mut I, name : &'static str, parser : impl FnOnce(&mut I) -> Result<T, ParseError>) -> Result<T, ParseError> {
+        input.rule(name, parser)
+    }
+}
+
+impl Input for Chars<'_> {
+    fn at(&self) -> At {
+        At::Remaining(self.as_str().len())
+    }
+}
+
+impl Input for CharIndices<'_> {
+    fn at(&self) -> At {
+        At::Offset(self.offset())
+    }
+}
+
+impl Input for Bytes<'_> { }
+impl<T> Input for std::vec::IntoIter<T> { }
+impl<T, const N : usize> Input for std::array::IntoIter<T, N> { }
+impl<T> Input for std::slice::Iter<'_, T> { }
+impl<'a, T : Copy + 'a> Input for std::iter::Copied<std::slice::Iter<'a, T>> { }
+impl<'a, T : Clone + 'a> Input for std::iter::Cloned<std::slice::Iter<'a, T>> { }
+impl<I : Input> Input for std::iter::Enumerate<I> { }
+impl<I : Input> Input for std::iter::Peekable<I> { }
+impl<I : Input> Input for std::iter::Skip<I> { }
+impl<I : Input> Input for std::iter::Take<I> { }
+impl<I : Input, F> Input for std::iter::Map<I, F> where Self : Iterator { }
+impl<I : Input, P> Input for std::iter::Filter<I, P> where Self : Iterator { }
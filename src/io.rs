@@ -0,0 +1,283 @@
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{ErrorKind, Read};
+use std::rc::Rc;
+
+use crate::{ParseError, Reason};
+use crate::input::Input;
+use crate::trace::At;
+
+const CHUNK : usize = 8 * 1024;
+
+#[derive(Debug)]
+struct Shared<R> {
+    reader : R,
+    buffer : VecDeque<u8>,
+    offset : usize,
+    eof : bool,
+    error : Option<std::io::Error>,
+    // Position => number of live inputs sitting at that position.  Bytes before the
+    // smallest position can no longer be rewound to and are dropped from the buffer.
+    positions : BTreeMap<usize, usize>,
+}
+
+impl<R : Read> Shared<R> {
+    fn fill(&mut self) -> bool {
+        if self.eof || self.error.is_some() {
+            return false;
+        }
+        let mut chunk = [0u8; CHUNK];
+        loop {
+            match self.reader.read(&mut chunk) {
+                Ok(0) => { self.eof = true; return false; },
+                Ok(n) => { self.buffer.extend(&chunk[..n]); return true; },
+                Err(e) if e.kind() == ErrorKind::Interrupted => { },
+                Err(e) => { self.error = Some(e); return false; },
+            }
+        }
+    }
+}
+
+impl<R> Shared<R> {
+    fn enter(&mut self, index : usize) {
+        *self.positions.entry(index).or_insert(0) += 1;
+    }
+
+    fn leave(&mut self, index : usize) {
+        if let Some(count) = self.positions.get_mut(&index) {
+            *count -= 1;
+            if *count == 0 {
+                self.positions.remove(&index);
+            }
+        }
+        if let Some((&min, _)) = self.positions.first_key_value() {
+            let unreachable = min - self.offset;
+            self.buffer.drain(..unreachable);
+            self.offset = min;
+        }
+    }
+}
+
+// A failed read ends the byte stream just like end of input does, so the bytes
+// are only handed out by `ReadInput::parse`, which turns such a failure into
+// `Reason::Io` instead of letting the parser see a clean end.
+#[derive(Debug)]
+pub struct ReadInput<R> {
+    cursor : ReadCursor<R>,
+}
+
+#[derive(Debug)]
+pub struct ReadCursor<R> {
+    shared : Rc<RefCell<Shared<R>>>,
+    index : usize,
+}
+
+impl<R : Read> ReadInput<R> {
+    pub fn new(reader : R) -> Self {
+        let mut shared = Shared {
+            reader,
+            buffer : VecDeque::new(),
+            offset : 0,
+            eof : false,
+            error : None,
+            positions : BTreeMap::new(),
+        };
+        shared.enter(0);
+        ReadInput { cursor : ReadCursor { shared : Rc::new(RefCell::new(shared)), index : 0 } }
+    }
+
+    pub fn position(&self) -> usize {
+        self.cursor.position()
+    }
+
+    pub fn error(&self) -> Option<ErrorKind> {
+        self.cursor.shared.borrow().error.as_ref().map(|e| e.kind())
+    }
+
+    pub fn parse<T>(&mut self, parser : impl FnOnce(&mut ReadCursor<R>) -> Result<T, ParseError>) -> Result<T, ParseError> {
+        let mut input = self.cursor.clone();
+        let result = parser(&mut input);
+
+        if let Some(kind) = self.error() {
+            return Err(ParseError::Fatal(vec![Reason::Io(kind)]));
+        }
+
+        if result.is_ok() {
+            std::mem::swap(&mut self.cursor, &mut input);
+        }
+        result
+    }
+}
+
+impl<R> ReadCursor<R> {
+    pub fn position(&self) -> usize {
+        self.index
+    }
+}
+
+impl<R> Clone for ReadCursor<R> {
+    fn clone(&self) -> Self {
+        self.shared.borrow_mut().enter(self.index);
+        ReadCursor { shared : Rc::clone(&self.shared), index : self.index }
+    }
+}
+
+impl<R> Drop for ReadCursor<R> {
+    fn drop(&mut self) {
+        self.shared.borrow_mut().leave(self.index);
+    }
+}
+
+impl<R : Read> Input for ReadCursor<R> {
+    fn at(&self) -> At {
+        At::Offset(self.position())
+    }
+}
+
+impl<R : Read> Iterator for ReadCursor<R> {
+    type Item = u8;
+
+    fn next(&mut self) -> Option<u8> {
+        let mut shared = self.shared.borrow_mut();
+        if self.index == shared.offset + shared.buffer.len() && !shared.fill() {
+            return None;
+        }
+        let byte = shared.buffer[self.index - shared.offset];
+        shared.enter(self.index + 1);
+        shared.leave(self.index);
+        self.index += 1;
+        Some(byte)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::*;
+
+    struct Trickle<'a> {
+        data : &'a [u8],
+        fail_at : Option<usize>,
+        read : usize,
+    }
+
+    impl<'a> Read for Trickle<'a> {
+        fn read(&mut self, buf : &mut [u8]) -> std::io::Result<usize> {
+            if Some(self.read) == self.fail_at {
+                return Err(std::io::Error::new(ErrorKind::ConnectionReset, "reset"));
+            }
+            match self.data.split_first() {
+                Some((b, rest)) if !buf.is_empty() => {
+                    buf[0] = *b;
+                    self.data = rest;
+                    self.read += 1;
+                    Ok(1)
+                },
+                _ => Ok(0),
+            }
+        }
+    }
+
+    fn trickle(data : &[u8]) -> Trickle<'_> {
+        Trickle { data, fail_at : None, read : 0 }
+    }
+
+    fn byte_a(input : &mut impl Iterator<Item = u8>) -> Result<u8, ParseError> {
+        match input.next() {
+            Some(b'a') => Ok(b'a'),
+            _ => Err(ParseError::Error),
+        }
+    }
+
+    fn byte_b(input : &mut impl Iterator<Item = u8>) -> Result<u8, ParseError> {
+        match input.next() {
+            Some(b'b') => Ok(b'b'),
+            _ => Err(ParseError::Error),
+        }
+    }
+
+    fn a_or_b(input : &mut (impl Iterator<Item = u8> + Clone)) -> Result<u8, ParseError> {
+        alt!(input => byte_b; byte_a)
+    }
+
+    #[test]
+    fn read_input_should_parse() {
+        let mut input = ReadInput::new(trickle(b"aab"));
+
+        let output = input.parse(|input| parser!(input => {
+            xs <= * a_or_b;
+            end;
+            select xs
+        })).expect("the parse should be successful");
+
+        assert_eq!(output, b"aab");
+    }
+
+    #[test]
+    fn read_input_should_rewind_on_failure() {
+        let mut input = ReadInput::new(trickle(b"aac"));
+
+        let output = input.parse(|input| parser!(input => {
+            one <= byte_a;
+            two <= byte_a;
+            three <= byte_b;
+            select (one, two, three)
+        }));
+
+        assert!( matches!(output, Err(ParseError::Error)) );
+        assert_eq!( input.position(), 0 );
+
+        let output = input.parse(|input| Ok((input.next(), input.next(), input.next()))).expect("the parse should be successful");
+
+        assert_eq!( output, (Some(b'a'), Some(b'a'), Some(b'c')) );
+    }
+
+    #[test]
+    fn read_input_should_only_retain_reachable_data() {
+        let mut input = ReadInput::new(trickle(b"aaaa")).cursor;
+
+        input.next();
+        input.next();
+        assert_eq!( input.shared.borrow().buffer.len(), 0 );
+
+        let rp = input.clone();
+        input.next();
+        input.next();
+        assert_eq!( input.shared.borrow().buffer.len(), 2 );
+
+        drop(rp);
+        assert_eq!( input.shared.borrow().buffer.len(), 0 );
+    }
+
+    #[test]
+    fn read_input_should_report_io_error_as_fatal() {
+        let mut input = ReadInput::new(Trickle { data : b"aaaa", fail_at : Some(2), read : 0 });
+
+        let output = input.parse(|input| parser!(input => {
+            xs <= * byte_a;
+            select xs
+        }));
+
+        if let Err(ParseError::Fatal(reasons)) = output {
+            assert_eq!(reasons, [Reason::Io(ErrorKind::ConnectionReset)]);
+        }
+        else {
+            panic!();
+        }
+    }
+
+    #[test]
+    fn read_input_should_not_treat_io_error_as_end() {
+        let mut input = ReadInput::new(Trickle { data : b"aaaa", fail_at : Some(2), read : 0 });
+
+        let output = input.parse(|input| parser!(input => {
+            xs <= * byte_a;
+            end;
+            select xs
+        }));
+
+        assert!( matches!(output, Err(ParseError::Fatal(_))) );
+        assert_eq!( input.error(), Some(ErrorKind::ConnectionReset) );
+    }
+}
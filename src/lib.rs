@@ -1,17 +1,37 @@
 
+pub mod partial;
+pub mod io;
+pub mod text;
+pub mod number;
+pub mod string;
+pub mod cst;
+pub mod incremental;
+pub mod dynamic;
+pub mod trace;
+pub mod input;
+pub mod recursive;
+pub mod combinator;
+pub mod project;
+
 #[derive(Debug)]
 pub enum ParseError {
     Error,
     Fatal(Vec<Reason>),
+    Incomplete { needed : usize },
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Reason {
     Alt,
     Where,
     End,
     Fatal,
     Rule(&'static str),
+    Io(std::io::ErrorKind),
+    Overflow(&'static str),
+    Escape,
+    Unterminated,
+    Message(String),
 }
 
 impl std::fmt::Display for Reason {
@@ -23,6 +43,11 @@ impl std::fmt::Display for Reason {
             End => write!(f, "End"),
             Fatal => write!(f, "Fatal"),
             Rule(r) => write!(f, "Rule: {}", r),
+            Io(kind) => write!(f, "Io: {}", kind),
+            Overflow(t) => write!(f, "Overflow: literal does not fit in {}", t),
+            Escape => write!(f, "Escape"),
+            Unterminated => write!(f, "Unterminated"),
+            Message(m) => write!(f, "Message: {}", m),
         }
     }
 }
@@ -33,76 +58,495 @@ impl std::fmt::Display for ParseError {
         match self {
             Error => write!(f, "Error"),
             Fatal(reasons) => write!(f, "Fatal: {}", reasons.iter().map(|r| format!("{}", r)).collect::<Vec<_>>().join("\n")),
+            Incomplete { needed } => write!(f, "Incomplete: {} more needed", needed),
         }
     }
 }
 
 impl std::error::Error for ParseError {}
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Span {
+    pub start : usize,
+    pub end : usize,
+}
+
+// `fn` wraps a whole function body, which may `return` or use `?`, so under the
+// `trace` feature it runs in a closure.  Everything else is traced in place.
+#[cfg(feature = "trace")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! traced {
+    (fn $name:expr, $input:ident, $body:expr) => {
+        {
+            #[allow(unused_imports)]
+            use $crate::trace::{Located as _, Unknown as _};
+            let at = (&&$crate::trace::Position(&*$input)).at();
+            $crate::trace::rule($name, at, $input, |$input| $body)
+        }
+    };
+
+    ($name:expr, $input:ident, $body:expr) => {
+        {
+            #[allow(unused_imports)]
+            use $crate::trace::{Located as _, Unknown as _};
+            let scope = $crate::trace::enter($name, (&&$crate::trace::Position(&*$input)).at());
+            let ret = $body;
+            scope.exit(&ret);
+            ret
+        }
+    };
+}
+
+#[cfg(not(feature = "trace"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! traced {
+    (fn $name:expr, $input:ident, $body:expr) => { $body };
+    ($name:expr, $input:ident, $body:expr) => { $body };
+}
+
 #[macro_export]
 macro_rules! pat {
-    ($vis:vis $name:ident <$life:lifetime> : $in:ty => $out:ty = ! $pattern : pat => $e:expr) => {
-        $vis fn $name<$life>(input : &mut impl Iterator<Item = $in>) -> Result<$out, ParseError> {
-            match input.next() {
-                Some($pattern) => Ok($e),
-                _ => Err(ParseError::Fatal(vec![Reason::Rule(stringify!($name))])),
-            }
+    (@skip before before $skip:expr, $input:ident) => { $crate::skip_trivia($input, $skip)?; };
+    (@skip after after $skip:expr, $input:ident) => { $crate::skip_trivia($input, $skip)?; };
+    (@skip $at:ident $mode:ident $skip:expr, $input:ident) => { };
+
+    (@trivia $mode:ident $skip:expr ; $vis:vis $name:ident <$life:lifetime> : $in:ty => $out:ty = ! $($pattern:pat $(if $guard:expr)? => $e:expr),+ $(,)?) => {
+        $vis fn $name<$life>(input : &mut (impl $crate::input::Input<Item = $in> + Clone)) -> Result<$out, ParseError> {
+            $crate::traced!(fn stringify!($name), input, {
+                pat!(@skip before $mode $skip, input);
+                let ret = match input.next() {
+                    $(Some($pattern) $(if $guard)? => $e,)+
+                    _ => return Err(ParseError::Fatal(vec![Reason::Rule(stringify!($name))])),
+                };
+                pat!(@skip after $mode $skip, input);
+                Ok(ret)
+            })
         }
     };
 
-    ($vis:vis $name:ident <$life:lifetime> : $in:ty => $out:ty = $pattern : pat => $e:expr) => {
-        $vis fn $name<$life>(input : &mut impl Iterator<Item = $in>) -> Result<$out, ParseError> {
-            match input.next() {
-                Some($pattern) => Ok($e),
-                _ => Err(ParseError::Error),
+    (@trivia $mode:ident $skip:expr ; $vis:vis $name:ident <$life:lifetime> : $in:ty => $out:ty = $($pattern:pat $(if $guard:expr)? => $e:expr),+ $(,)?) => {
+        $vis fn $name<$life>(input : &mut (impl $crate::input::Input<Item = $in> + Clone)) -> Result<$out, ParseError> {
+            $crate::traced!(fn stringify!($name), input, {
+                pat!(@skip before $mode $skip, input);
+                let ret = match input.next() {
+                    $(Some($pattern) $(if $guard)? => $e,)+
+                    _ => return Err(ParseError::Error),
+                };
+                pat!(@skip after $mode $skip, input);
+                Ok(ret)
+            })
+        }
+    };
+
+    (@trivia $mode:ident $skip:expr ; $vis:vis $name:ident : $in:ty => $out:ty = ! $($pattern:pat $(if $guard:expr)? => $e:expr),+ $(,)?) => {
+        pat!(@trivia $mode $skip ; $vis $name <'a> : $in => $out = ! $($pattern $(if $guard)? => $e),+);
+    };
+
+    (@trivia $mode:ident $skip:expr ; $vis:vis $name:ident : $in:ty => $out:ty = $($pattern:pat $(if $guard:expr)? => $e:expr),+ $(,)?) => {
+        pat!(@trivia $mode $skip ; $vis $name <'a> : $in => $out = $($pattern $(if $guard)? => $e),+);
+    };
+
+    (@next $input:ident, $pattern:pat) => { $input.next() };
+
+    ($vis:vis $name:ident <$life:lifetime> : impl Project<$in:ty> => $out:ty = ! $($pattern:pat $(if $guard:expr)? => $e:expr),+ $(,)?) => {
+        $vis fn $name<$life>(input : &mut impl $crate::input::Input<Item = impl $crate::project::Project<$in>>) -> Result<$out, ParseError> {
+            $crate::traced!(fn stringify!($name), input, {
+                match input.next().as_ref().map($crate::project::Project::<$in>::project) {
+                    $(Some($pattern) $(if $guard)? => Ok($e),)+
+                    _ => Err(ParseError::Fatal(vec![Reason::Rule(stringify!($name))])),
+                }
+            })
+        }
+    };
+
+    ($vis:vis $name:ident <$life:lifetime> : impl Project<$in:ty> => $out:ty = $($pattern:pat $(if $guard:expr)? => $e:expr),+ $(,)?) => {
+        $vis fn $name<$life>(input : &mut impl $crate::input::Input<Item = impl $crate::project::Project<$in>>) -> Result<$out, ParseError> {
+            $crate::traced!(fn stringify!($name), input, {
+                match input.next().as_ref().map($crate::project::Project::<$in>::project) {
+                    $(Some($pattern) $(if $guard)? => Ok($e),)+
+                    _ => Err(ParseError::Error),
+                }
+            })
+        }
+    };
+
+    ($vis:vis $name:ident : impl Project<$in:ty> => $out:ty = ! $($pattern:pat $(if $guard:expr)? => $e:expr),+ $(,)?) => {
+        pat!($vis $name <'a> : impl Project<$in> => $out = ! $($pattern $(if $guard)? => $e),+);
+    };
+
+    ($vis:vis $name:ident : impl Project<$in:ty> => $out:ty = $($pattern:pat $(if $guard:expr)? => $e:expr),+ $(,)?) => {
+        pat!($vis $name <'a> : impl Project<$in> => $out = $($pattern $(if $guard)? => $e),+);
+    };
+
+    ($vis:vis $name:ident <$life:lifetime> : $in:ty => $out:ty = ! peek $($pattern:pat $(if $guard:expr)? => $e:expr),+ $(,)?) => {
+        $vis fn $name<$life>(input : &mut (impl $crate::input::Input<Item = $in> + Clone)) -> Result<$out, ParseError> {
+            $crate::traced!(fn stringify!($name), input, {
+                match input.clone().next() {
+                    $(Some($pattern) $(if $guard)? => Ok($e),)+
+                    _ => Err(ParseError::Fatal(vec![Reason::Rule(stringify!($name))])),
+                }
+            })
+        }
+    };
+
+    ($vis:vis $name:ident <$life:lifetime> : $in:ty => $out:ty = peek $($pattern:pat $(if $guard:expr)? => $e:expr),+ $(,)?) => {
+        $vis fn $name<$life>(input : &mut (impl $crate::input::Input<Item = $in> + Clone)) -> Result<$out, ParseError> {
+            $crate::traced!(fn stringify!($name), input, {
+                match input.clone().next() {
+                    $(Some($pattern) $(if $guard)? => Ok($e),)+
+                    _ => Err(ParseError::Error),
+                }
+            })
+        }
+    };
+
+    ($vis:vis $name:ident <$life:lifetime> : $in:ty => $out:ty = ! $first:pat, $($pattern:pat),+ $(if $guard:expr)? => $e:expr) => {
+        $vis fn $name<$life>(input : &mut (impl $crate::input::Input<Item = $in> + Clone)) -> Result<$out, ParseError> {
+            $crate::traced!(fn stringify!($name), input, {
+                let mut rp = input.clone();
+                match (input.next(), $(pat!(@next input, $pattern)),+) {
+                    (Some($first), $(Some($pattern)),+) $(if $guard)? => Ok($e),
+                    _ => {
+                        std::mem::swap(input, &mut rp);
+                        Err(ParseError::Fatal(vec![Reason::Rule(stringify!($name))]))
+                    },
+                }
+            })
+        }
+    };
+
+    ($vis:vis $name:ident <$life:lifetime> : $in:ty => $out:ty = $first:pat, $($pattern:pat),+ $(if $guard:expr)? => $e:expr) => {
+        $vis fn $name<$life>(input : &mut (impl $crate::input::Input<Item = $in> + Clone)) -> Result<$out, ParseError> {
+            $crate::traced!(fn stringify!($name), input, {
+                let mut rp = input.clone();
+                match (input.next(), $(pat!(@next input, $pattern)),+) {
+                    (Some($first), $(Some($pattern)),+) $(if $guard)? => Ok($e),
+                    _ => {
+                        std::mem::swap(input, &mut rp);
+                        Err(ParseError::Error)
+                    },
+                }
+            })
+        }
+    };
+
+    ($vis:vis $name:ident <$life:lifetime> : $in:ty => $out:ty = ! $($pattern:pat $(if $guard:expr)? => $e:expr),+ $(,)?) => {
+        $vis fn $name<$life>(input : &mut impl $crate::input::Input<Item = $in>) -> Result<$out, ParseError> {
+            $crate::traced!(fn stringify!($name), input, {
+                match input.next() {
+                    $(Some($pattern) $(if $guard)? => Ok($e),)+
+                    _ => Err(ParseError::Fatal(vec![Reason::Rule(stringify!($name))])),
+                }
+            })
+        }
+    };
+
+    ($vis:vis $name:ident <$life:lifetime> : $in:ty => $out:ty = $($pattern:pat $(if $guard:expr)? => $e:expr),+ $(,)?) => {
+        $vis fn $name<$life>(input : &mut impl $crate::input::Input<Item = $in>) -> Result<$out, ParseError> {
+            $crate::traced!(fn stringify!($name), input, {
+                match input.next() {
+                    $(Some($pattern) $(if $guard)? => Ok($e),)+
+                    _ => Err(ParseError::Error),
+                }
+            })
+        }
+    };
+
+    ($vis:vis $name:ident : $in:ty => $out:ty = ! peek $($pattern:pat $(if $guard:expr)? => $e:expr),+ $(,)?) => {
+        pat!($vis $name <'a> : $in => $out = ! peek $($pattern $(if $guard)? => $e),+)
+    };
+
+    ($vis:vis $name:ident : $in:ty => $out:ty = peek $($pattern:pat $(if $guard:expr)? => $e:expr),+ $(,)?) => {
+        pat!($vis $name <'a> : $in => $out = peek $($pattern $(if $guard)? => $e),+)
+    };
+
+    ($vis:vis $name:ident : $in:ty => $out:ty = ! $first:pat, $($pattern:pat),+ $(if $guard:expr)? => $e:expr) => {
+        pat!($vis $name <'a> : $in => $out = ! $first, $($pattern),+ $(if $guard)? => $e)
+    };
+
+    ($vis:vis $name:ident : $in:ty => $out:ty = $first:pat, $($pattern:pat),+ $(if $guard:expr)? => $e:expr) => {
+        pat!($vis $name <'a> : $in => $out = $first, $($pattern),+ $(if $guard)? => $e)
+    };
+
+    ($vis:vis $name:ident : $in:ty => $out:ty = ! $($pattern:pat $(if $guard:expr)? => $e:expr),+ $(,)?) => {
+        pat!($vis $name <'a> : $in => $out = ! $($pattern $(if $guard)? => $e),+)
+    };
+
+    ($vis:vis $name:ident : $in:ty => $out:ty = $($pattern:pat $(if $guard:expr)? => $e:expr),+ $(,)?) => {
+        pat!($vis $name <'a> : $in => $out = $($pattern $(if $guard)? => $e),+)
+    };
+}
+
+#[macro_export]
+macro_rules! trivia {
+    (before $skip:expr => { $(pat!($($rule:tt)*);)* }) => {
+        $(pat!(@trivia before $skip ; $($rule)*);)*
+    };
+
+    (after $skip:expr => { $(pat!($($rule:tt)*);)* }) => {
+        $(pat!(@trivia after $skip ; $($rule)*);)*
+    };
+}
+
+pub fn skip_trivia<I : Iterator + Clone, T>(input : &mut I, mut trivia : impl FnMut(&mut I) -> Result<T, ParseError>) -> Result<(), ParseError> {
+    let mut rp = input.clone();
+    match trivia(input) {
+        Ok(_) => Ok(()),
+        Err(ParseError::Error) => { std::mem::swap(input, &mut rp); Ok(()) },
+        Err(e) => Err(e),
+    }
+}
+
+// Each action gets the value its matcher produced; string literal rules produce
+// the literal and the `_` fallback gets the unmatched character as text.
+#[macro_export]
+macro_rules! lexer {
+    ($vis:vis $name:ident => $token:ty { $($rules:tt)* }) => {
+        lexer!(@rules $vis $name $token [] $($rules)*);
+    };
+
+    (@rules $vis:vis $name:ident $token:ty [$($acc:tt)*] _ => $error:expr ; ) => {
+        lexer!(@emit $vis $name $token [$($acc)*] $error);
+    };
+
+    (@rules $vis:vis $name:ident $token:ty [$($acc:tt)*] $lit:literal => skip ; $($rest:tt)*) => {
+        lexer!(@rules $vis $name $token [$($acc)* ($crate::text::literal($lit), |_| None)] $($rest)*);
+    };
+
+    (@rules $vis:vis $name:ident $token:ty [$($acc:tt)*] $lit:literal => $make:expr ; $($rest:tt)*) => {
+        lexer!(@rules $vis $name $token [$($acc)* ($crate::text::literal($lit), |value| Some(($make)(value)))] $($rest)*);
+    };
+
+    (@rules $vis:vis $name:ident $token:ty [$($acc:tt)*] $matcher:expr => skip ; $($rest:tt)*) => {
+        lexer!(@rules $vis $name $token [$($acc)* ($matcher, |_| None)] $($rest)*);
+    };
+
+    (@rules $vis:vis $name:ident $token:ty [$($acc:tt)*] $matcher:expr => $make:expr ; $($rest:tt)*) => {
+        lexer!(@rules $vis $name $token [$($acc)* ($matcher, |value| Some(($make)(value)))] $($rest)*);
+    };
+
+    (@emit $vis:vis $name:ident $token:ty [$(($matcher:expr, $action:expr))*] $error:expr) => {
+        #[derive(Debug, Clone)]
+        $vis struct $name<'a> {
+            source : &'a str,
+            offset : usize,
+        }
+
+        impl<'a> $name<'a> {
+            $vis fn new(source : &'a str) -> Self {
+                $name { source, offset : 0 }
             }
         }
+
+        impl<'a> $crate::input::Input for $name<'a> {
+            fn at(&self) -> $crate::trace::At {
+                $crate::trace::At::Offset(self.offset)
+            }
+        }
+
+        impl<'a> Iterator for $name<'a> {
+            type Item = ($crate::Span, $token);
+
+            // Matchers are only compared by length here.  The winner runs again so
+            // that its action gets the value it produced.
+            #[allow(unused_assignments)]
+            fn next(&mut self) -> Option<Self::Item> {
+                loop {
+                    let rest = &self.source[self.offset..];
+                    let first = rest.chars().next()?;
+
+                    let mut best : Option<(usize, usize)> = None;
+                    let mut index = 0;
+                    $(
+                        let mut input = rest.chars();
+                        if $matcher(&mut input).is_ok() {
+                            let len = rest.len() - input.as_str().len();
+                            if len > 0 && best.map_or(true, |(l, _)| len > l) {
+                                best = Some((len, index));
+                            }
+                        }
+                        index += 1;
+                    )*
+
+                    let (len, winner) = match best {
+                        Some(best) => best,
+                        None => {
+                            let len = first.len_utf8();
+                            let span = $crate::Span { start : self.offset, end : self.offset + len };
+                            self.offset += len;
+                            return Some((span, ($error)(&rest[..len])));
+                        },
+                    };
+                    let span = $crate::Span { start : self.offset, end : self.offset + len };
+                    self.offset += len;
+
+                    let mut index = 0;
+                    $(
+                        if index == winner {
+                            let value = $matcher(&mut rest.chars()).ok().expect("the matcher succeeded on this text above");
+                            match ($action)(value) {
+                                Some(token) => { return Some((span, token)); },
+                                None => { continue; },
+                            }
+                        }
+                        index += 1;
+                    )*
+                }
+            }
+        }
+    };
+}
+
+// `rule!(name : Item => Out = { .. })` defines a rule with a `parser!` body and
+// `rule!(name : Item => Out = alt(a; b))` one with an `alt!` body.  The body is
+// expanded as `parser!(input as name => { .. })` or `alt!(input as name => a; b)`,
+// which is also how hand written parsers give their name to the trace.  The input
+// has to implement `input::Input`, which turns every rule into a node named after
+// it when parsing into a `Cst`.
+//
+// Parameterized rules list their parameters after the name and return a parser
+// instead of being one: `p => T` takes a parser producing `T` and `n : T` takes a
+// plain value.  Inside the body the input type is available as `RuleInput`.
+/// ```
+/// use renounce::*;
+///
+/// rule!(parens<T>(p => T) : char => T = {
+///     _o <= text::literal("(");
+///     x <= p;
+///     _c <= ! text::literal(")");
+///     select x
+/// });
+///
+/// let mut input = "(7)".chars();
+/// let x = parens(text::digit)(&mut input).expect("the parse should be successful");
+/// assert_eq!(x, '7');
+/// ```
+#[macro_export]
+macro_rules! rule {
+    (@named $name:ident, $e:expr) => {
+        match $e {
+            Err(ParseError::Fatal(mut reasons)) => {
+                reasons.push(Reason::Rule(stringify!($name)));
+                Err(ParseError::Fatal(reasons))
+            },
+            result => result,
+        }
+    };
+
+    (@body $name:ident, $input:ident, { $($body:tt)* }) => {
+        parser!($input as $name => { $($body)* })
     };
 
-    ($vis:vis $name:ident : $in:ty => $out:ty = ! $pattern : pat => $e:expr) => {
-        pat!($vis $name <'a> : $in => $out = ! $pattern => $e)
+    (@body $name:ident, $input:ident, alt($($parser:expr);*)) => {
+        alt!($input as $name => $($parser);*)
     };
 
-    ($vis:vis $name:ident : $in:ty => $out:ty = $pattern : pat => $e:expr) => {
-        pat!($vis $name <'a> : $in => $out = $pattern => $e)
+    (@param $i:ident, : $t:ty) => { $t };
+    (@param $i:ident, => $t:ty) => { impl FnMut(&mut $i) -> Result<$t, ParseError> };
+
+    ($vis:vis $name:ident <$life:lifetime> : $in:ty => $out:ty = $($body:tt)*) => {
+        $vis fn $name<$life>(input : &mut (impl $crate::input::Input<Item = $in> + Clone)) -> Result<$out, ParseError> {
+            rule!(@named $name, $crate::input::Input::rule(input, stringify!($name), |input| rule!(@body $name, input, $($body)*)))
+        }
+    };
+
+    ($vis:vis $name:ident $(<$($gen:ident),*>)? ($($param:ident $kind:tt $t:ty),*) : $in:ty => $out:ty = $($body:tt)*) => {
+        #[allow(unused_mut)]
+        $vis fn $name<RuleInput $($(, $gen)*)?>($(mut $param : rule!(@param RuleInput, $kind $t)),*) -> impl FnMut(&mut RuleInput) -> Result<$out, ParseError>
+            where RuleInput : $crate::input::Input<Item = $in> + Clone
+        {
+            move |input : &mut RuleInput| rule!(@named $name, $crate::input::Input::rule(input, stringify!($name), |input| rule!(@body $name, input, $($body)*)))
+        }
+    };
+
+    ($vis:vis $name:ident : $in:ty => $out:ty = $($body:tt)*) => {
+        $vis fn $name<'a>(input : &mut (impl $crate::input::Input<Item = $in> + Clone)) -> Result<$out, ParseError> {
+            #[allow(unused_imports)]
+            use $crate::input::{Cached as _, Uncached as _};
+            let output = $crate::input::Output::<$out>(std::marker::PhantomData);
+            rule!(@named $name, (&&output).run(input, stringify!($name), |input| rule!(@body $name, input, $($body)*)))
+        }
+    };
+}
+
+// There is deliberately no way to declare an error type: every rule returns
+// `ParseError`, which is what `parser!` and `alt!` need to compose rules.
+#[macro_export]
+macro_rules! grammar {
+    ($vis:vis item $item:ty; $($rules:tt)*) => {
+        grammar!(@rules [$vis] [$item] $($rules)*);
+    };
+
+    (@rules [$vis:vis] [$item:ty]) => { };
+
+    (@rules [$vis:vis] [$item:ty] rule $name:ident -> $out:ty = { $($body:tt)* }; $($rest:tt)*) => {
+        rule!($vis $name : $item => $out = { $($body)* });
+        grammar!(@rules [$vis] [$item] $($rest)*);
+    };
+
+    (@rules [$vis:vis] [$item:ty] rule $name:ident -> $out:ty = alt($($parser:expr);*); $($rest:tt)*) => {
+        rule!($vis $name : $item => $out = alt($($parser);*));
+        grammar!(@rules [$vis] [$item] $($rest)*);
     };
 }
 
 #[macro_export]
 macro_rules! alt { 
-    ($input:ident => $($parser:expr);* ) => {
-        'alt : {
+    (@named $name:expr, $input:ident => $($parser:expr);* ) => {
+        {
             use std::borrow::BorrowMut;
             let input = $input.borrow_mut();
+            $crate::traced!($name, input, 'alt : {
+                $(
+                    let mut rp = input.clone();
+                    match $parser(input) {
+                        Ok(x) => { break 'alt Ok(x); },
+                        Err(ParseError::Error) => { std::mem::swap(input, &mut rp); },
+                        Err(ParseError::Fatal(mut reasons)) => { 
+                            reasons.push(Reason::Alt);
+                            break 'alt Err(ParseError::Fatal(reasons));
+                        },
+                        Err(e @ ParseError::Incomplete { .. }) => { break 'alt Err(e); },
+                    }
+                )*
+
+                Err(ParseError::Error)
+            })
+        }
+    };
 
-            $(
-                let mut rp = input.clone();
-                match $parser(input) {
-                    Ok(x) => { break 'alt Ok(x); },
-                    Err(ParseError::Error) => { std::mem::swap(input, &mut rp); },
-                    Err(ParseError::Fatal(mut reasons)) => { 
-                        reasons.push(Reason::Alt);
-                        break 'alt Err(ParseError::Fatal(reasons));
-                    },
-                }
-            )*
+    ($input:ident as $name:ident => $($parser:expr);* ) => {
+        alt!(@named stringify!($name), $input => $($parser);*)
+    };
 
-            Err(ParseError::Error)
-        }
+    ($input:ident => $($parser:expr);* ) => {
+        alt!(@named concat!("alt! at ", file!(), ":", line!()), $input => $($parser);*)
     };
 }
 
 #[macro_export]
 macro_rules! parser {
-    ($input:ident => { $($rest:tt)* } )  => {
+    (@named $name:expr, $input:ident => { $($rest:tt)* } )  => {
         {
             use std::borrow::BorrowMut;
             let input = $input.borrow_mut();
-            let mut _rp = input.clone();
-            parser!(input, _rp, $($rest)*)
+            $crate::traced!($name, input, {
+                let mut _rp = input.clone();
+                parser!(input, _rp, $($rest)*)
+            })
         }
     };
 
+    ($input:ident as $name:ident => { $($rest:tt)* } )  => {
+        parser!(@named stringify!($name), $input => { $($rest)* })
+    };
+
+    ($input:ident => { $($rest:tt)* } )  => {
+        parser!(@named concat!("parser! at ", file!(), ":", line!()), $input => { $($rest)* })
+    };
+
     ($input:ident, $rp:ident, ! where $e:expr; $($rest:tt)*) => {
         if $e {
             parser!($input, $rp, $($rest)*)
@@ -136,6 +580,21 @@ macro_rules! parser {
         }
     };
 
+    ($input:ident, $rp:ident, if $cond:expr => { $($then:tt)* } else { $($else:tt)* }) => {
+        if $cond {
+            parser!($input, $rp, $($then)*)
+        }
+        else {
+            parser!($input, $rp, $($else)*)
+        }
+    };
+
+    ($input:ident, $rp:ident, match $e:expr => { $($p:pat $(if $guard:expr)? => { $($arm:tt)* } $(,)?)* }) => {
+        match $e {
+            $($p $(if $guard)? => { parser!($input, $rp, $($arm)*) },)*
+        }
+    };
+
     ($input:ident, $rp:ident, $a:ident <= ! $ma:expr; $($rest:tt)*) => {
         {
             let mut rp = $input.clone();
@@ -151,6 +610,7 @@ macro_rules! parser {
                     std::mem::swap($input, &mut rp);
                     Err(ParseError::Fatal(vec![Reason::Rule(stringify!($a))])) 
                 }, 
+                Err(e @ ParseError::Incomplete { .. }) => { Err(e) },
             }
         }
     };
@@ -170,6 +630,7 @@ macro_rules! parser {
                         reasons.push(Reason::Rule(stringify!($a)));
                         break 'zero_or_more Err(ParseError::Fatal(reasons));
                     },
+                    Err(e @ ParseError::Incomplete { .. }) => { break 'zero_or_more Err(e); },
                 }
             }
             let $a = ret;
@@ -177,6 +638,46 @@ macro_rules! parser {
         }
     };
 
+    ($input:ident, $rp:ident, $a:ident <= fold($init:expr, $f:expr) $ma:expr; $($rest:tt)*) => {
+        'fold : {
+            let mut acc = $init;
+            loop {
+                let mut peek = $input.clone();
+                match $ma($input) {
+                    Ok(x) => { acc = $f(acc, x); },
+                    Err(ParseError::Error) => {
+                        std::mem::swap($input, &mut peek); 
+                        break;
+                    },
+                    Err(ParseError::Fatal(mut reasons)) => {
+                        reasons.push(Reason::Rule(stringify!($a)));
+                        break 'fold Err(ParseError::Fatal(reasons));
+                    },
+                    Err(e @ ParseError::Incomplete { .. }) => { break 'fold Err(e); },
+                }
+            }
+            let $a = acc;
+            parser!($input, $rp, $($rest)*)
+        }
+    };
+
+    ($input:ident, $rp:ident, skip * $ma:expr; $($rest:tt)*) => {
+        'skip : {
+            loop {
+                let mut peek = $input.clone();
+                match $ma($input) {
+                    Ok(_) => { },
+                    Err(ParseError::Error) => {
+                        std::mem::swap($input, &mut peek); 
+                        break;
+                    },
+                    Err(e) => { break 'skip Err(e); },
+                }
+            }
+            parser!($input, $rp, $($rest)*)
+        }
+    };
+
     ($input:ident, $rp:ident, $a:ident <= ? $ma:expr; $($rest:tt)*) => {
         {
             let mut rp = $input.clone();
@@ -195,6 +696,7 @@ macro_rules! parser {
                     reasons.push(Reason::Rule(stringify!($a)));
                     Err(ParseError::Fatal(reasons)) 
                 },
+                Err(e @ ParseError::Incomplete { .. }) => { Err(e) },
             }
         }
     };
@@ -212,6 +714,112 @@ macro_rules! parser {
                 std::mem::swap($input, &mut $rp); 
                 Err(ParseError::Error) 
             }, 
+            Err(e @ ParseError::Incomplete { .. }) => { Err(e) },
+        }
+    };
+
+    // `$t:ty` cannot be followed by `<=`, so the type's tokens are collected one at
+    // a time.
+    ($input:ident, $rp:ident, $a:ident : $($tail:tt)*) => {
+        parser!($input, $rp, @typed $a [] $($tail)*)
+    };
+
+    ($input:ident, $rp:ident, @typed $a:ident [$($t:tt)*] <= * $ma:expr; $($rest:tt)*) => {
+        'typed : {
+            let mut failure = None;
+            let $a : $($t)* = std::iter::from_fn(|| {
+                let mut peek = $input.clone();
+                match $ma($input) {
+                    Ok(x) => Some(x),
+                    Err(ParseError::Error) => {
+                        std::mem::swap($input, &mut peek);
+                        None
+                    },
+                    Err(e) => {
+                        failure = Some(e);
+                        None
+                    },
+                }
+            }).collect();
+            match failure {
+                Some(ParseError::Fatal(mut reasons)) => {
+                    reasons.push(Reason::Rule(stringify!($a)));
+                    break 'typed Err(ParseError::Fatal(reasons));
+                },
+                Some(e) => { break 'typed Err(e); },
+                None => { },
+            }
+            parser!($input, $rp, $($rest)*)
+        }
+    };
+
+    ($input:ident, $rp:ident, @typed $a:ident [$($t:tt)*] $x:tt $($tail:tt)*) => {
+        parser!($input, $rp, @typed $a [$($t)* $x] $($tail)*)
+    };
+
+    // `$ma:expr` cannot be followed by `until`, so the repeated parser's tokens are
+    // collected one at a time.
+    ($input:ident, $rp:ident, $a:ident <= ! * $($tail:tt)*) => {
+        parser!($input, $rp, @until fatal $a [] $($tail)*)
+    };
+
+    ($input:ident, $rp:ident, $a:ident <= * $($tail:tt)*) => {
+        parser!($input, $rp, @until error $a [] $($tail)*)
+    };
+
+    ($input:ident, $rp:ident, @until $kind:ident $a:ident [$($ma:tt)*] until $end:expr; $($rest:tt)*) => {
+        'until : {
+            parser!(@until $kind checkpoint $input, rp);
+            let mut ret = vec![];
+            let end = loop {
+                let mut peek = $input.clone();
+                match $end($input) {
+                    Ok(x) => { break x; },
+                    Err(ParseError::Error) => { std::mem::swap($input, &mut peek); },
+                    Err(ParseError::Fatal(mut reasons)) => {
+                        reasons.push(Reason::Rule(stringify!($a)));
+                        break 'until Err(ParseError::Fatal(reasons));
+                    },
+                    Err(e @ ParseError::Incomplete { .. }) => { break 'until Err(e); },
+                }
+                match ($($ma)*)($input) {
+                    Ok(x) => { ret.push(x); },
+                    Err(ParseError::Error) => {
+                        break 'until parser!(@until $kind $input, $rp, rp, $a);
+                    },
+                    Err(ParseError::Fatal(mut reasons)) => {
+                        reasons.push(Reason::Rule(stringify!($a)));
+                        break 'until Err(ParseError::Fatal(reasons));
+                    },
+                    Err(e @ ParseError::Incomplete { .. }) => { break 'until Err(e); },
+                }
+            };
+            let $a = (ret, end);
+            parser!($input, $rp, $($rest)*)
+        }
+    };
+
+    ($input:ident, $rp:ident, @until $kind:ident $a:ident [$($ma:tt)*] $t:tt $($tail:tt)*) => {
+        parser!($input, $rp, @until $kind $a [$($ma)* $t] $($tail)*)
+    };
+
+    (@until error checkpoint $input:ident, $local:ident) => { };
+
+    (@until fatal checkpoint $input:ident, $local:ident) => {
+        let mut $local = $input.clone();
+    };
+
+    (@until error $input:ident, $rp:ident, $local:ident, $a:ident) => {
+        {
+            std::mem::swap($input, &mut $rp);
+            Err(ParseError::Error)
+        }
+    };
+
+    (@until fatal $input:ident, $rp:ident, $local:ident, $a:ident) => {
+        {
+            std::mem::swap($input, &mut $local);
+            Err(ParseError::Fatal(vec![Reason::Rule(stringify!($a))]))
         }
     };
 
@@ -239,14 +847,33 @@ macro_rules! parser {
         }
     };
 
+    ($input:ident, $rp:ident, ! select ? $e:expr) => {
+        match $e {
+            Ok(x) => Ok(x),
+            Err(e) => Err(ParseError::Fatal(vec![Reason::Message(e.to_string())])),
+        }
+    };
+
+    ($input:ident, $rp:ident, select ? $e:expr) => {
+        match $e {
+            Ok(x) => Ok(x),
+            Err(_) => {
+                std::mem::swap($input, &mut $rp);
+                Err(ParseError::Error)
+            },
+        }
+    };
+
     ($input:ident, $rp:ident, select $e:expr) => {
         Ok($e)
     };
 }
 
 #[cfg(test)]
+#[allow(clippy::assertions_on_constants, clippy::into_iter_on_ref, clippy::redundant_pattern_matching)]
 mod test {
     use super::*;
+    use crate::combinator::Parser;
     use std::str::Chars;
 
     fn return_fatal(_input : &mut impl Iterator<Item = char>) -> Result<char, ParseError> {
@@ -335,89 +962,212 @@ mod test {
 
         pat!(p<'a> : (usize, &'a Option<u8>) => u8 = ! (_, Some(x)) => x + 1);
 
-        let output = p(&mut input).expect("the parse should be successful");
+        let output = p(&mut input).expect("the parse should be successful");
+
+        assert_eq!( output, 5 );
+    }
+
+    #[test]
+    fn fatal_pat_should_fatal_error() {
+        let input = [Some(4)];
+        let mut input = input.into_iter();
+
+        pat!(p : Option<u8> => u8 = ! Some(5) => 5);
+
+        let output = p(&mut input);
+
+        assert!( matches!(output, Err(ParseError::Fatal(_))) );
+    }
+
+    #[test]
+    fn fatal_pat_should_create_parser() {
+        let input = [Some(4)];
+        let mut input = input.into_iter();
+
+        pat!(p : Option<u8> => u8 = ! Some(x) => x + 1);
+
+        let output = p(&mut input).expect("the parse should be successful");
+
+        assert_eq!(output, 5);
+    }
+
+    #[test]
+    fn fatal_pat_should_create_parser_usable_parser() {
+        let input = [Some(4)];
+        let mut input = input.into_iter();
+
+        pat!(p : Option<u8> => u8 = ! Some(x) => x + 1);
+
+        let output = parser!(input => {
+            a <= p;
+            where a == 5;
+            select a
+        }).expect("the parse should be successful");
+
+        assert_eq!(output, 5);
+    }
+
+    #[test]
+    fn pat_should_error() {
+        let input = [Some(4)];
+        let mut input = input.into_iter();
+
+        pat!(p : Option<u8> => u8 = Some(5) => 5);
+
+        let output = p(&mut input);
+
+        assert!( matches!(output, Err(ParseError::Error)) );
+    }
+
+    #[test]
+    fn pat_should_create_parser() {
+        let input = [Some(4)];
+        let mut input = input.into_iter();
+
+        pat!(p : Option<u8> => u8 = Some(x) => x + 1);
+
+        let output = p(&mut input).expect("the parse should be successful");
+
+        assert_eq!(output, 5);
+    }
+
+    #[test]
+    fn pat_should_create_parser_usable_parser() {
+        let input = [Some(4)];
+        let mut input = input.into_iter();
+
+        pat!(p : Option<u8> => u8 = Some(x) => x + 1);
+
+        let output = parser!(input => {
+            a <= p;
+            where a == 5;
+            select a
+        }).expect("the parse should be successful");
+
+        assert_eq!(output, 5);
+    }
+
+    #[test]
+    fn pat_should_respect_guard() {
+        let input = [Some(4), Some(0)];
+        let mut input = input.into_iter();
+
+        pat!(p : Option<u8> => u8 = Some(x) if x > 0 => x);
+
+        assert_eq!(p(&mut input).expect("the parse should be successful"), 4);
+        assert!( matches!(p(&mut input), Err(ParseError::Error)) );
+    }
+
+    #[test]
+    fn pat_should_match_multiple_arms() {
+        let input = [Some(4), None, Some(0)];
+        let mut input = input.into_iter();
+
+        pat!(p : Option<u8> => i16 = Some(x) if x > 0 => x as i16, None => -1, Some(_) => 0);
+
+        let output = parser!(input => {
+            xs <= * p;
+            end;
+            select xs
+        }).expect("the parse should be successful");
 
-        assert_eq!( output, 5 );
+        assert_eq!(output, [4, -1, 0]);
     }
 
     #[test]
-    fn fatal_pat_should_fatal_error() {
-        let input = [Some(4)];
+    fn fatal_pat_should_fatal_when_no_arm_matches() {
+        let input = [Some(0)];
         let mut input = input.into_iter();
 
-        pat!(p : Option<u8> => u8 = ! Some(5) => 5);
+        pat!(p : Option<u8> => u8 = ! Some(x) if x > 0 => x, None => 0,);
 
         let output = p(&mut input);
 
-        assert!( matches!(output, Err(ParseError::Fatal(_))) );
+        if let Err(ParseError::Fatal(reasons)) = output {
+            assert_eq!(reasons, [Reason::Rule("p")]);
+        }
+        else {
+            panic!();
+        }
     }
 
     #[test]
-    fn fatal_pat_should_create_parser() {
-        let input = [Some(4)];
-        let mut input = input.into_iter();
+    fn peek_pat_should_not_consume() {
+        let input = "-x";
+        let mut input = input.chars();
 
-        pat!(p : Option<u8> => u8 = ! Some(x) => x + 1);
+        pat!(sign : char => bool = peek '-' => true, '+' => false);
 
-        let output = p(&mut input).expect("the parse should be successful");
+        let output = parser!(input => {
+            negative <= sign;
+            c <= any_char;
+            select (negative, c)
+        }).expect("the parse should be successful");
 
-        assert_eq!(output, 5);
+        assert_eq!(output, (true, '-'));
+        assert!( matches!(sign(&mut "x".chars()), Err(ParseError::Error)) );
     }
 
     #[test]
-    fn fatal_pat_should_create_parser_usable_parser() {
-        let input = [Some(4)];
-        let mut input = input.into_iter();
+    fn fatal_peek_pat_should_fatal() {
+        let input = "x";
+        let mut input = input.chars();
 
-        pat!(p : Option<u8> => u8 = ! Some(x) => x + 1);
+        pat!(sign : char => () = ! peek '-' => ());
 
-        let output = parser!(input => {
-            a <= p;
-            where a == 5;
-            select a
-        }).expect("the parse should be successful");
+        let output = sign(&mut input);
 
-        assert_eq!(output, 5);
+        if let Err(ParseError::Fatal(reasons)) = output {
+            assert_eq!(reasons, [Reason::Rule("sign")]);
+            assert_eq!(input.as_str(), "x");
+        }
+        else {
+            panic!();
+        }
     }
 
     #[test]
-    fn pat_should_error() {
-        let input = [Some(4)];
-        let mut input = input.into_iter();
+    fn sequence_pat_should_match_all() {
+        let input = "->x";
+        let mut input = input.chars();
 
-        pat!(p : Option<u8> => u8 = Some(5) => 5);
+        pat!(arrow : char => () = '-', '>' => ());
 
-        let output = p(&mut input);
+        let output = parser!(input => {
+            _a <= arrow;
+            c <= any_char;
+            select c
+        }).expect("the parse should be successful");
 
-        assert!( matches!(output, Err(ParseError::Error)) );
+        assert_eq!(output, 'x');
     }
 
     #[test]
-    fn pat_should_create_parser() {
-        let input = [Some(4)];
-        let mut input = input.into_iter();
+    fn sequence_pat_should_bind_and_guard() {
+        let input = "a1";
+        let mut input = input.chars();
 
-        pat!(p : Option<u8> => u8 = Some(x) => x + 1);
+        pat!(pair : char => (char, char) = a, d if d.is_ascii_digit() => (a, d));
 
-        let output = p(&mut input).expect("the parse should be successful");
+        let output = pair(&mut input).expect("the parse should be successful");
 
-        assert_eq!(output, 5);
+        assert_eq!(output, ('a', '1'));
+        assert!( matches!(pair(&mut "ab".chars()), Err(ParseError::Error)) );
     }
 
     #[test]
-    fn pat_should_create_parser_usable_parser() {
-        let input = [Some(4)];
-        let mut input = input.into_iter();
+    fn sequence_pat_should_restore_on_partial_match() {
+        let input = "-x";
+        let mut input = input.chars();
 
-        pat!(p : Option<u8> => u8 = Some(x) => x + 1);
+        pat!(arrow : char => () = '-', '>' => ());
+        pat!(fatal_arrow : char => () = ! '-', '>' => ());
 
-        let output = parser!(input => {
-            a <= p;
-            where a == 5;
-            select a
-        }).expect("the parse should be successful");
+        assert!( matches!(arrow(&mut input), Err(ParseError::Error)) );
+        assert_eq!( input.as_str(), "-x" );
 
-        assert_eq!(output, 5);
+        assert!( matches!(fatal_arrow(&mut input), Err(ParseError::Fatal(_))) );
+        assert_eq!( input.as_str(), "-x" );
     }
 
     #[test]
@@ -723,77 +1473,365 @@ mod test {
     }
 
     #[test]
-    fn end_failure_should_reset_input() {
-        let input = "yyz";
+    fn end_failure_should_reset_input() {
+        let input = "yyz";
+        let mut input = input.chars();
+
+        let output = parser!(input => {
+            one <= parse_y;
+            two <= parse_y;
+            end;
+            select (one, two)
+        });
+
+        assert!( matches!( output, Err(ParseError::Error) ) );
+        assert_eq!( input.next(), Some('y') );
+        assert_eq!( input.next(), Some('y') );
+        assert_eq!( input.next(), Some('z') );
+    }
+
+    #[test]
+    fn alt_failure_should_reset_input() {
+        let input = "x";
+        let mut input = input.chars();
+
+        let output = alt!(input => parse_y; parse_z);
+
+        assert!( matches!( output, Err(ParseError::Error) ) );
+        assert_eq!( input.next(), Some('x') );
+    }
+
+    #[test]
+    fn zero_or_more_failure_should_reset_input() {
+        let input = "x";
+        let mut input = input.chars();
+
+        let output = parser!(input => {
+            one <= * parse_y;
+            select one
+        });
+
+        assert!( matches!( output, Ok(_) ) );
+        assert_eq!( input.next(), Some('x') );
+    }
+
+    #[test]
+    fn maybe_failure_should_reset_input() {
+        let input = "x";
+        let mut input = input.chars();
+
+        let output = parser!(input => {
+            one <= ? parse_y;
+            select one
+        });
+
+        assert!( matches!( output, Ok(_) ) );
+        assert_eq!( input.next(), Some('x') );
+    }
+
+    #[test]
+    fn fatal_where_failure_should_not_reset_input() {
+        let input = "yyz";
+        let mut input = input.chars();
+
+        let output = parser!(input => {
+            one <= parse_y;
+            two <= parse_y;
+            ! where false;
+            select (one, two)
+        });
+
+        assert!( matches!( output, Err(ParseError::Fatal(_)) ) );
+        assert_eq!( input.next(), Some('z') );
+    } 
+
+    #[test]
+    fn select_try_should_succeed() {
+        let input = "42";
+        let mut input = input.chars();
+
+        let output = parser!(input => {
+            n <= number::integer::<u32>;
+            select? u8::try_from(n)
+        }).expect("the parse should be successful");
+
+        assert_eq!(output, 42u8);
+    }
+
+    #[test]
+    fn select_try_failure_should_reset_input() {
+        let input = "300";
+        let mut input = input.chars();
+
+        let output = parser!(input => {
+            n <= number::integer::<u32>;
+            select? u8::try_from(n)
+        });
+
+        assert!( matches!( output, Err(ParseError::Error) ) );
+        assert_eq!( input.as_str(), "300" );
+    }
+
+    #[test]
+    fn fatal_select_try_should_carry_message() {
+        let input = "300";
+        let mut input = input.chars();
+
+        let output = parser!(input => {
+            n <= number::integer::<u32>;
+            ! select? u8::try_from(n)
+        });
+
+        if let Err(ParseError::Fatal(reasons)) = output {
+            assert_eq!(reasons, [Reason::Message(u8::try_from(300u32).unwrap_err().to_string())]);
+            assert_eq!(input.as_str(), "");
+        }
+        else {
+            panic!();
+        }
+    }
+
+    #[test]
+    fn if_should_choose_branch() {
+        fn value(input : &mut Chars) -> Result<(char, Option<char>), ParseError> {
+            parser!(input => {
+                h <= any_char;
+                if h == 's' => {
+                    select (h, None)
+                }
+                else {
+                    x <= any_char;
+                    select (h, Some(x))
+                }
+            })
+        }
+
+        assert_eq!(value(&mut "sx".chars()).expect("the parse should be successful"), ('s', None));
+        assert_eq!(value(&mut "lx".chars()).expect("the parse should be successful"), ('l', Some('x')));
+    }
+
+    #[test]
+    fn if_branch_failure_should_reset_to_start() {
+        let input = "l";
+        let mut input = input.chars();
+
+        let output = parser!(input => {
+            h <= any_char;
+            if h == 's' => {
+                select None
+            }
+            else {
+                x <= any_char;
+                select Some(x)
+            }
+        });
+
+        assert!( matches!( output, Err(ParseError::Error) ) );
+        assert_eq!( input.as_str(), "l" );
+    }
+
+    #[test]
+    fn match_should_choose_arm() {
+        fn value(input : &mut Chars) -> Result<u32, ParseError> {
+            parser!(input => {
+                h <= any_char;
+                match h => {
+                    'a' => { select 0 }
+                    d if d.is_ascii_digit() => {
+                        x <= any_char;
+                        where x.is_ascii_digit();
+                        select d.to_digit(10).unwrap() * 10 + x.to_digit(10).unwrap()
+                    },
+                    _ => {
+                        ! where false;
+                        select 1
+                    }
+                }
+            })
+        }
+
+        let mut input = "a1".chars();
+        assert_eq!(value(&mut input).expect("the parse should be successful"), 0);
+        assert_eq!(input.as_str(), "1");
+
+        assert_eq!(value(&mut "42".chars()).expect("the parse should be successful"), 42);
+
+        let mut input = "4x".chars();
+        assert!( matches!( value(&mut input), Err(ParseError::Error) ) );
+        assert_eq!( input.as_str(), "4x" );
+
+        assert!( matches!( value(&mut "x".chars()), Err(ParseError::Fatal(_)) ) );
+    }
+
+    #[test]
+    fn fold_should_accumulate() {
+        let input = "1234x";
+        let mut input = input.chars();
+
+        let output = parser!(input => {
+            n <= fold(0u32, |acc, d : char| acc * 10 + d.to_digit(10).unwrap()) text::digit;
+            select n
+        }).expect("the parse should be successful");
+
+        assert_eq!(output, 1234);
+        assert_eq!(input.as_str(), "x");
+    }
+
+    #[test]
+    fn fold_should_pass_through_fatal() {
+        let input = "yyz";
+        let mut input = input.chars();
+
+        let output = parser!(input => {
+            n <= fold(0, |acc, _| acc + 1) return_fatal;
+            select n
+        });
+
+        if let Err(ParseError::Fatal(reasons)) = output {
+            assert_eq!(reasons, [Reason::Fatal, Reason::Rule("n")]);
+        }
+        else {
+            panic!();
+        }
+    }
+
+    #[test]
+    fn skip_should_discard_results() {
+        let input = "   x";
+        let mut input = input.chars();
+
+        let output = parser!(input => {
+            skip * text::ws;
+            x <= any_char;
+            select x
+        }).expect("the parse should be successful");
+
+        assert_eq!(output, 'x');
+    }
+
+    #[test]
+    fn skip_should_pass_through_fatal() {
+        let input = "yyz";
+        let mut input = input.chars();
+
+        let output = parser!(input => {
+            skip * return_fatal;
+            select ()
+        });
+
+        assert!( matches!( output, Err(ParseError::Fatal(_)) ) );
+    }
+
+    #[test]
+    fn until_should_stop_at_terminator() {
+        let input = "ab*/c";
+        let mut input = input.chars();
+
+        let output = parser!(input => {
+            body <= * any_char until text::literal("*/");
+            select body
+        }).expect("the parse should be successful");
+
+        assert_eq!(output, (vec!['a', 'b'], "*/"));
+        assert_eq!(input.as_str(), "c");
+    }
+
+    #[test]
+    fn until_should_accept_combinator_expressions() {
+        let input = "a1b2;";
+        let mut input = input.chars();
+
+        let output = parser!(input => {
+            body <= * text::alpha.then(text::digit) until text::literal(";");
+            end;
+            select body.0
+        }).expect("the parse should be successful");
+
+        assert_eq!(output, [('a', '1'), ('b', '2')]);
+    }
+
+    #[test]
+    fn until_should_reset_input_when_input_ends() {
+        let input = "yab";
         let mut input = input.chars();
 
         let output = parser!(input => {
-            one <= parse_y;
-            two <= parse_y;
-            end;
-            select (one, two)
+            _y <= parse_y;
+            body <= * any_char until text::literal("*/");
+            select body
         });
 
         assert!( matches!( output, Err(ParseError::Error) ) );
-        assert_eq!( input.next(), Some('y') );
-        assert_eq!( input.next(), Some('y') );
-        assert_eq!( input.next(), Some('z') );
+        assert_eq!( input.as_str(), "yab" );
     }
 
     #[test]
-    fn alt_failure_should_reset_input() {
-        let input = "x";
+    fn fatal_until_should_fatal_when_input_ends() {
+        let input = "yab";
         let mut input = input.chars();
 
-        let output = alt!(input => parse_y; parse_z);
+        let output = parser!(input => {
+            _y <= parse_y;
+            body <= ! * any_char until text::literal("*/");
+            select body
+        });
 
-        assert!( matches!( output, Err(ParseError::Error) ) );
-        assert_eq!( input.next(), Some('x') );
+        if let Err(ParseError::Fatal(reasons)) = output {
+            assert_eq!(reasons, [Reason::Rule("body")]);
+            assert_eq!(input.as_str(), "ab");
+        }
+        else {
+            panic!();
+        }
     }
 
     #[test]
-    fn zero_or_more_failure_should_reset_input() {
-        let input = "x";
+    fn typed_repetition_should_collect() {
+        let input = "abc1 x";
         let mut input = input.chars();
 
         let output = parser!(input => {
-            one <= * parse_y;
-            select one
-        });
+            name : String <= * text::alnum;
+            _w <= text::ws1;
+            set : std::collections::BTreeSet<char> <= * any_char;
+            select (name, set)
+        }).expect("the parse should be successful");
 
-        assert!( matches!( output, Ok(_) ) );
-        assert_eq!( input.next(), Some('x') );
+        assert_eq!(output, ("abc1".to_string(), ['x'].into_iter().collect()));
     }
 
     #[test]
-    fn maybe_failure_should_reset_input() {
-        let input = "x";
+    fn typed_repetition_should_collect_nested_generics() {
+        let input = "a1b2";
         let mut input = input.chars();
 
         let output = parser!(input => {
-            one <= ? parse_y;
-            select one
-        });
+            map : std::collections::HashMap<char, Vec<char>> <= * text::alpha.then(text::digit).map(|(a, d)| (a, vec![d]));
+            end;
+            select map
+        }).expect("the parse should be successful");
 
-        assert!( matches!( output, Ok(_) ) );
-        assert_eq!( input.next(), Some('x') );
+        assert_eq!(output[&'a'], ['1']);
+        assert_eq!(output[&'b'], ['2']);
     }
 
     #[test]
-    fn fatal_where_failure_should_not_reset_input() {
+    fn typed_repetition_should_pass_through_fatal() {
         let input = "yyz";
         let mut input = input.chars();
 
         let output = parser!(input => {
-            one <= parse_y;
-            two <= parse_y;
-            ! where false;
-            select (one, two)
+            xs : String <= * return_fatal;
+            select xs
         });
 
-        assert!( matches!( output, Err(ParseError::Fatal(_)) ) );
-        assert_eq!( input.next(), Some('z') );
-    } 
+        if let Err(ParseError::Fatal(reasons)) = output {
+            assert_eq!(reasons, [Reason::Fatal, Reason::Rule("xs")]);
+        }
+        else {
+            panic!();
+        }
+    }
 
     #[test]
     fn fatal_rule_failure_should_not_reset_input() {
@@ -1039,4 +2077,441 @@ mod test {
             assert!(false);
         }
     }
-}
\ No newline at end of file
+
+    fn trivia(input : &mut (impl Iterator<Item = char> + Clone)) -> Result<(), ParseError> {
+        fn piece(input : &mut (impl Iterator<Item = char> + Clone)) -> Result<(), ParseError> {
+            alt!(input => text::ws1; text::line_comment("//"); text::block_comment("/*", "*/"))
+        }
+
+        parser!(input => {
+            _t <= * piece;
+            select ()
+        })
+    }
+
+    #[test]
+    fn trivia_before_should_skip_trivia_before_tokens() {
+        trivia!(before trivia => {
+            pat!(open : char => char = '(' => '(');
+            pat!(close : char => char = ! ')' => ')');
+        });
+
+        let input = " /* a /* b */ */ ( // c\n )";
+        let mut input = input.chars();
+
+        let output = parser!(input => {
+            o <= open;
+            c <= close;
+            end;
+            select (o, c)
+        }).expect("the parse should be successful");
+
+        assert_eq!(output, ('(', ')'));
+    }
+
+    #[test]
+    fn trivia_after_should_skip_trivia_after_tokens() {
+        trivia!(after trivia => {
+            pat!(open : char => char = '(' => '(');
+        });
+
+        let input = "( // c\n x";
+        let mut input = input.chars();
+
+        let output = open(&mut input).expect("the parse should be successful");
+
+        assert_eq!(output, '(');
+        assert_eq!(input.as_str(), "x");
+    }
+
+    #[test]
+    fn trivia_token_failure_should_reset_trivia() {
+        trivia!(before trivia => {
+            pat!(open : char => char = '(' => '(');
+        });
+
+        let input = "  x";
+        let mut input = input.chars();
+
+        let output = parser!(input => {
+            o <= open;
+            select o
+        });
+
+        assert!( matches!(output, Err(ParseError::Error)) );
+        assert_eq!( input.as_str(), "  x" );
+    }
+
+    #[test]
+    fn trivia_should_pass_through_fatal() {
+        trivia!(before trivia => {
+            pat!(open : char => char = '(' => '(');
+        });
+
+        let input = " /* ( ";
+        let mut input = input.chars();
+
+        let output = open(&mut input);
+
+        assert!( matches!(output, Err(ParseError::Fatal(_))) );
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    enum Token {
+        Let,
+        Eq,
+        Semi,
+        Ident(String),
+        Num(u64),
+        Unknown(String),
+    }
+
+    lexer!(Lexer => Token {
+        "let" => |_| Token::Let;
+        "=" => |_| Token::Eq;
+        ";" => |_| Token::Semi;
+        text::ident => Token::Ident;
+        number::integer::<u64> => Token::Num;
+        text::ws1 => skip;
+        text::line_comment("//") => skip;
+        _ => |s : &str| Token::Unknown(s.to_string());
+    });
+
+    #[test]
+    fn lexer_should_produce_spanned_tokens() {
+        let output = Lexer::new("let x = 1_0; // done").collect::<Vec<_>>();
+
+        assert_eq!(output, [
+            (Span { start : 0, end : 3 }, Token::Let),
+            (Span { start : 4, end : 5 }, Token::Ident("x".into())),
+            (Span { start : 6, end : 7 }, Token::Eq),
+            (Span { start : 8, end : 11 }, Token::Num(10)),
+            (Span { start : 11, end : 12 }, Token::Semi),
+        ]);
+    }
+
+    #[test]
+    fn lexer_should_pass_matcher_values_to_actions() {
+        let output = Lexer::new("0x10 0b1_1").map(|(_, t)| t).collect::<Vec<_>>();
+
+        assert_eq!(output, [Token::Num(16), Token::Num(3)]);
+    }
+
+    #[test]
+    fn lexer_should_prefer_longest_match_then_first_rule() {
+        let output = Lexer::new("letter let").map(|(_, t)| t).collect::<Vec<_>>();
+
+        assert_eq!(output, [Token::Ident("letter".into()), Token::Let]);
+    }
+
+    #[test]
+    fn lexer_should_produce_error_tokens() {
+        let output = Lexer::new("x $ y").map(|(_, t)| t).collect::<Vec<_>>();
+
+        assert_eq!(output, [Token::Ident("x".into()), Token::Unknown("$".into()), Token::Ident("y".into())]);
+    }
+
+    #[test]
+    fn lexer_should_plug_into_parser() {
+        pat!(let_kw : (Span, Token) => () = (_, Token::Let) => ());
+        pat!(eq : (Span, Token) => () = ! (_, Token::Eq) => ());
+        pat!(semi : (Span, Token) => () = ! (_, Token::Semi) => ());
+        pat!(ident : (Span, Token) => String = (_, Token::Ident(x)) => x);
+        pat!(num : (Span, Token) => u64 = (_, Token::Num(n)) => n);
+
+        fn value(input : &mut (impl input::Input<Item = (Span, Token)> + Clone)) -> Result<u64, ParseError> {
+            alt!(input => num; |i| ident(i).map(|x| x.len() as u64))
+        }
+
+        fn binding(input : &mut (impl input::Input<Item = (Span, Token)> + Clone)) -> Result<(String, u64), ParseError> {
+            parser!(input => {
+                _l <= let_kw;
+                name <= ident;
+                _e <= eq;
+                v <= ! value;
+                _s <= semi;
+                select (name, v)
+            })
+        }
+
+        let mut input = Lexer::new("let a = 5; let b = abc;");
+
+        let output = parser!(input => {
+            bs <= * binding;
+            end;
+            select bs
+        }).expect("the parse should be successful");
+
+        assert_eq!(output, [("a".to_string(), 5), ("b".to_string(), 3)]);
+    }
+
+    #[test]
+    fn grammar_should_define_mutually_recursive_rules() {
+        mod arith {
+            use super::*;
+
+            grammar! {
+                pub item char;
+
+                rule expr -> i64 = {
+                    first <= term;
+                    rest <= * plus_term;
+                    select first + rest.into_iter().sum::<i64>()
+                };
+
+                rule plus_term -> i64 = {
+                    _w <= text::ws0;
+                    _p <= text::literal("+");
+                    _w <= text::ws0;
+                    t <= ! term;
+                    select t
+                };
+
+                rule term -> i64 = alt(number; parens);
+
+                rule number -> i64 = {
+                    n <= number::integer::<i64>;
+                    select n
+                };
+
+                rule parens -> i64 = {
+                    _o <= text::literal("(");
+                    e <= ! expr;
+                    _c <= ! text::literal(")");
+                    select e
+                };
+            }
+        }
+
+        let input = "1 + (2 + (3+4)) + 5";
+        let mut input = input.chars();
+
+        let output = parser!(input => {
+            e <= arith::expr;
+            end;
+            select e
+        }).expect("the parse should be successful");
+
+        assert_eq!(output, 15);
+    }
+
+    #[test]
+    fn grammar_rules_should_keep_fatal_semantics() {
+        grammar! {
+            item char;
+
+            rule parens -> char = {
+                _o <= text::literal("(");
+                x <= ! text::alpha;
+                select x
+            };
+        }
+
+        let input = "(1";
+        let mut input = input.chars();
+
+        let output = parens(&mut input);
+
+        assert!( matches!(output, Err(ParseError::Fatal(_))) );
+    }
+
+    #[test]
+    fn rule_should_define_parser() {
+        rule!(ident : char => String = {
+            first <= text::alpha;
+            rest <= * text::alnum;
+            select std::iter::once(first).chain(rest).collect()
+        });
+
+        let input = "ab1 c";
+        let mut input = input.chars();
+
+        let output = ident(&mut input).expect("the parse should be successful");
+
+        assert_eq!(output, "ab1");
+    }
+
+    #[test]
+    fn pub_rule_should_be_pub() {
+        mod m {
+            use super::*;
+            rule!(pub y_or_z : char => char = alt(parse_y; parse_z));
+        }
+
+        let input = "z";
+        let mut input = input.chars();
+
+        let output = m::y_or_z(&mut input).expect("the parse should be successful");
+
+        assert_eq!(output, 'z');
+    }
+
+    #[test]
+    fn rule_should_handle_borrowed_data() {
+        pat!(byte<'a> : &'a u8 => u8 = x => *x);
+
+        rule!(sum<'a> : &'a u8 => u8 = {
+            xs <= * byte;
+            select xs.into_iter().sum()
+        });
+
+        let input = [1, 2, 3];
+        let mut input = input.iter();
+
+        let output = sum(&mut input).expect("the parse should be successful");
+
+        assert_eq!(output, 6);
+    }
+
+    #[test]
+    fn rule_should_trace_rule_name() {
+        rule!(inner : char => char = {
+            y <= ! parse_y;
+            select y
+        });
+
+        rule!(outer : char => char = alt(parse_z; inner));
+
+        let input = "x";
+        let mut input = input.chars();
+
+        let output = parser!(input => {
+            o <= outer;
+            select o
+        });
+
+        if let Err(ParseError::Fatal(reasons)) = output {
+            assert_eq!(reasons, [Reason::Rule("y"), Reason::Rule("inner"), Reason::Alt, Reason::Rule("outer"), Reason::Rule("o")]);
+        }
+        else {
+            panic!();
+        }
+    }
+
+    rule!(parens<T>(p => T) : char => T = {
+        _o <= text::literal("(");
+        x <= p;
+        _c <= ! text::literal(")");
+        select x
+    });
+
+    rule!(list_of<X, T, S>(p => T, sep => S) : X => Vec<T> = {
+        first <= ? p;
+        rest <= * |input : &mut RuleInput| parser!(input => {
+            _s <= sep;
+            x <= ! p;
+            select x
+        });
+        select first.into_iter().chain(rest).collect()
+    });
+
+    rule!(repeat<T>(count : usize, p => T) : char => Vec<T> = {
+        xs <= * p;
+        where xs.len() == count;
+        select xs
+    });
+
+    fn nested(input : &mut (impl input::Input<Item = char> + Clone)) -> Result<u32, ParseError> {
+        alt!(input => parens(nested); |i| parens(number::integer::<u32>)(i))
+    }
+
+    #[test]
+    fn parametric_rule_should_parse() {
+        let input = "(1,2,3)";
+        let mut input = input.chars();
+
+        let output = parser!(input => {
+            xs <= parens(list_of(number::integer::<u32>, text::literal(",")));
+            end;
+            select xs
+        }).expect("the parse should be successful");
+
+        assert_eq!(output, [1, 2, 3]);
+    }
+
+    #[test]
+    fn parametric_rule_should_support_recursion() {
+        let input = "(((7)))";
+        let mut input = input.chars();
+
+        let output = nested(&mut input).expect("the parse should be successful");
+
+        assert_eq!(output, 7);
+    }
+
+    #[test]
+    fn parametric_rule_should_support_value_parameters() {
+        let input = "yyy";
+        let mut input = input.chars();
+
+        let output = repeat(3, parse_y)(&mut input).expect("the parse should be successful");
+
+        assert_eq!(output, ['y', 'y', 'y']);
+        assert!( matches!(repeat(2, parse_y)(&mut "yyy".chars()), Err(ParseError::Error)) );
+    }
+
+    #[test]
+    fn parametric_rule_should_support_generic_items() {
+        pat!(num : Option<u8> => u8 = Some(x) => x);
+        pat!(none : Option<u8> => () = None => ());
+
+        let input = [Some(1), None, Some(2)];
+        let mut input = input.into_iter();
+
+        let output = list_of(num, none)(&mut input).expect("the parse should be successful");
+
+        assert_eq!(output, [1, 2]);
+    }
+
+    #[test]
+    fn parametric_rule_should_trace_rule_name() {
+        let input = "(x";
+        let mut input = input.chars();
+
+        let output = parens(parse_y)(&mut input);
+
+        assert!( matches!(output, Err(ParseError::Error)) );
+
+        let input = "(y";
+        let mut input = input.chars();
+
+        let output = parens(parse_y)(&mut input);
+
+        if let Err(ParseError::Fatal(reasons)) = output {
+            assert_eq!(reasons, [Reason::Rule("_c"), Reason::Rule("parens")]);
+        }
+        else {
+            panic!();
+        }
+    }
+
+    #[test]
+    fn parser_should_allow_break_and_return_out_of_it() {
+        let input = "aab";
+        let mut input = input.chars();
+
+        let mut count = 0;
+        'outer : loop {
+            let _ = parser!(input => {
+                c <= text::any;
+                where { if c == 'b' { break 'outer; } true };
+                select c
+            });
+            count += 1;
+        }
+
+        assert_eq!(count, 2);
+
+        fn first_digit(input : &mut Chars) -> Option<char> {
+            let _ = parser!(input => {
+                c <= text::any;
+                where { if c.is_ascii_digit() { return Some(c); } false };
+                select c
+            });
+            None
+        }
+
+        assert_eq!(first_digit(&mut "7".chars()), Some('7'));
+        assert_eq!(first_digit(&mut "x".chars()), None);
+    }
+}
@@ -0,0 +1,237 @@
+
+use crate::{ParseError, Reason};
+use crate::text::next_if;
+
+pub trait Integer : Sized {
+    const NAME : &'static str;
+    const SIGNED : bool;
+    fn zero() -> Self;
+    fn push_digit(self, radix : u32, digit : u32, negative : bool) -> Option<Self>;
+}
+
+macro_rules! integer {
+    ($($t:ty),*) => {
+        $(
+            impl Integer for $t {
+                const NAME : &'static str = stringify!($t);
+                const SIGNED : bool = <$t>::MIN != 0;
+
+                fn zero() -> Self { 0 }
+
+                fn push_digit(self, radix : u32, digit : u32, negative : bool) -> Option<Self> {
+                    let x = self.checked_mul(radix as $t)?;
+                    if negative {
+                        x.checked_sub(digit as $t)
+                    }
+                    else {
+                        x.checked_add(digit as $t)
+                    }
+                }
+            }
+        )*
+    };
+}
+
+integer!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize);
+
+pub trait Float : Sized {
+    const NAME : &'static str;
+    fn from_literal(literal : &str) -> Option<Self>;
+}
+
+macro_rules! float {
+    ($($t:ty),*) => {
+        $(
+            impl Float for $t {
+                const NAME : &'static str = stringify!($t);
+
+                fn from_literal(literal : &str) -> Option<Self> {
+                    literal.parse::<$t>().ok().filter(|x| x.is_finite())
+                }
+            }
+        )*
+    };
+}
+
+float!(f32, f64);
+
+fn digits(input : &mut (impl Iterator<Item = char> + Clone), radix : u32, mut f : impl FnMut(u32) -> Result<(), ParseError>) -> Result<(), ParseError> {
+    match input.next().and_then(|c| c.to_digit(radix)) {
+        Some(d) => f(d)?,
+        None => return Err(ParseError::Error),
+    }
+    while let Some(c) = next_if(input, |c| *c == '_' || c.is_digit(radix)) {
+        if let Some(d) = c.to_digit(radix) {
+            f(d)?;
+        }
+    }
+    Ok(())
+}
+
+fn radix(input : &mut (impl Iterator<Item = char> + Clone)) -> u32 {
+    let mut peek = input.clone();
+    let radix = match (peek.next(), peek.next()) {
+        (Some('0'), Some('x')) => 16,
+        (Some('0'), Some('o')) => 8,
+        (Some('0'), Some('b')) => 2,
+        _ => return 10,
+    };
+    *input = peek;
+    while next_if(input, |c| *c == '_').is_some() { }
+    radix
+}
+
+pub fn integer<T : Integer>(input : &mut (impl Iterator<Item = char> + Clone)) -> Result<T, ParseError> {
+    let negative = T::SIGNED && next_if(input, |c| *c == '-').is_some();
+    let radix = radix(input);
+
+    let mut ret = Some(T::zero());
+    digits(input, radix, |d| {
+        ret = ret.take().and_then(|x| x.push_digit(radix, d, negative));
+        match ret {
+            Some(_) => Ok(()),
+            None => Err(ParseError::Fatal(vec![Reason::Overflow(T::NAME)])),
+        }
+    })?;
+
+    Ok(ret.expect("overflow is reported while parsing digits"))
+}
+
+fn decimal(input : &mut (impl Iterator<Item = char> + Clone), literal : &mut String) -> Result<(), ParseError> {
+    digits(input, 10, |d| {
+        literal.push(char::from_digit(d, 10).expect("decimal digit"));
+        Ok(())
+    })
+}
+
+pub fn float<T : Float>(input : &mut (impl Iterator<Item = char> + Clone)) -> Result<T, ParseError> {
+    let mut literal = String::new();
+    if let Some(sign) = next_if(input, |c| *c == '-' || *c == '+') {
+        literal.push(sign);
+    }
+    decimal(input, &mut literal)?;
+
+    let mut peek = input.clone();
+    let mut fraction = String::from(".");
+    if peek.next() == Some('.') && decimal(&mut peek, &mut fraction).is_ok() {
+        *input = peek;
+        literal.push_str(&fraction);
+    }
+
+    let mut peek = input.clone();
+    let mut exponent = String::from("e");
+    if next_if(&mut peek, |c| *c == 'e' || *c == 'E').is_some() {
+        if let Some(sign) = next_if(&mut peek, |c| *c == '-' || *c == '+') {
+            exponent.push(sign);
+        }
+        if decimal(&mut peek, &mut exponent).is_ok() {
+            *input = peek;
+            literal.push_str(&exponent);
+        }
+    }
+
+    T::from_literal(&literal).ok_or_else(|| ParseError::Fatal(vec![Reason::Overflow(T::NAME)]))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::*;
+
+    #[test]
+    fn integer_should_parse_decimal() {
+        let input = "1_000 ";
+        let mut input = input.chars();
+
+        let output = integer::<u32>(&mut input).expect("the parse should be successful");
+
+        assert_eq!(output, 1000);
+        assert_eq!(input.as_str(), " ");
+    }
+
+    #[test]
+    fn integer_should_parse_radix_prefixes() {
+        assert_eq!(integer::<u8>(&mut "0xff".chars()).expect("the parse should be successful"), 255);
+        assert_eq!(integer::<u16>(&mut "0o7_7".chars()).expect("the parse should be successful"), 63);
+        assert_eq!(integer::<u8>(&mut "0b1010".chars()).expect("the parse should be successful"), 10);
+        assert_eq!(integer::<u64>(&mut "0x_DEAD_beef".chars()).expect("the parse should be successful"), 0xdead_beef);
+    }
+
+    #[test]
+    fn integer_should_parse_signed_extremes() {
+        assert_eq!(integer::<i8>(&mut "-128".chars()).expect("the parse should be successful"), i8::MIN);
+        assert_eq!(integer::<i8>(&mut "127".chars()).expect("the parse should be successful"), i8::MAX);
+        assert_eq!(integer::<i64>(&mut "-0x10".chars()).expect("the parse should be successful"), -16);
+    }
+
+    #[test]
+    fn integer_should_fatal_on_overflow() {
+        let output = integer::<u8>(&mut "256".chars());
+
+        if let Err(ParseError::Fatal(reasons)) = output {
+            assert_eq!(reasons, [Reason::Overflow("u8")]);
+        }
+        else {
+            panic!();
+        }
+
+        assert!( matches!(integer::<i8>(&mut "-129".chars()), Err(ParseError::Fatal(_))) );
+    }
+
+    #[test]
+    fn integer_should_fail_without_digits() {
+        assert!( matches!(integer::<u32>(&mut "x".chars()), Err(ParseError::Error)) );
+        assert!( matches!(integer::<u32>(&mut "-1".chars()), Err(ParseError::Error)) );
+        assert!( matches!(integer::<u32>(&mut "_1".chars()), Err(ParseError::Error)) );
+    }
+
+    #[test]
+    fn integer_should_be_usable_in_parser() {
+        let input = "12,34";
+        let mut input = input.chars();
+
+        let output = parser!(input => {
+            a <= integer::<u32>;
+            _comma <= text::one_of(",");
+            b <= integer::<u32>;
+            select a + b
+        }).expect("the parse should be successful");
+
+        assert_eq!(output, 46);
+    }
+
+    #[test]
+    fn float_should_parse() {
+        assert_eq!(float::<f64>(&mut "1.5".chars()).expect("the parse should be successful"), 1.5);
+        assert_eq!(float::<f64>(&mut "-2_000.25e-1".chars()).expect("the parse should be successful"), -200.025);
+        assert_eq!(float::<f32>(&mut "3E2".chars()).expect("the parse should be successful"), 300.0);
+        assert_eq!(float::<f64>(&mut "7".chars()).expect("the parse should be successful"), 7.0);
+    }
+
+    #[test]
+    fn float_should_not_consume_trailing_dot_or_exponent() {
+        let input = "1.x";
+        let mut input = input.chars();
+
+        assert_eq!(float::<f64>(&mut input).expect("the parse should be successful"), 1.0);
+        assert_eq!(input.as_str(), ".x");
+
+        let input = "2e+";
+        let mut input = input.chars();
+
+        assert_eq!(float::<f64>(&mut input).expect("the parse should be successful"), 2.0);
+        assert_eq!(input.as_str(), "e+");
+    }
+
+    #[test]
+    fn float_should_fatal_on_overflow() {
+        let output = float::<f32>(&mut "1e39".chars());
+
+        if let Err(ParseError::Fatal(reasons)) = output {
+            assert_eq!(reasons, [Reason::Overflow("f32")]);
+        }
+        else {
+            panic!();
+        }
+    }
+}
@@ -0,0 +1,245 @@
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::ParseError;
+use crate::input::Input;
+use crate::trace::At;
+
+#[derive(Debug)]
+struct Buffer<T> {
+    items : Vec<T>,
+    offset : usize,
+    closed : bool,
+    wanted : usize,
+}
+
+// Input that is handed to the parser one chunk at a time.  Running out of
+// buffered items before the stream is closed makes `parse` report
+// `ParseError::Incomplete` instead of whatever the parser decided at the
+// (not yet real) end of input.  That check only happens in `parse`, so the items
+// are only handed out there, through a `PartialCursor`.
+//
+// A parser stops at the first missing item, so `needed` is how many more items
+// are needed at least; the parse may ask for more once they arrive.
+#[derive(Debug)]
+pub struct Partial<T> {
+    cursor : PartialCursor<T>,
+}
+
+#[derive(Debug)]
+pub struct PartialCursor<T> {
+    buffer : Rc<RefCell<Buffer<T>>>,
+    index : usize,
+}
+
+impl<T> Partial<T> {
+    pub fn new() -> Self {
+        let buffer = Rc::new(RefCell::new(Buffer { items : vec![], offset : 0, closed : false, wanted : 0 }));
+        Partial { cursor : PartialCursor { buffer, index : 0 } }
+    }
+
+    pub fn push(&mut self, item : T) {
+        let mut buffer = self.cursor.buffer.borrow_mut();
+        assert!(!buffer.closed, "cannot push onto a closed partial input");
+        buffer.items.push(item);
+    }
+
+    pub fn close(&mut self) {
+        self.cursor.buffer.borrow_mut().closed = true;
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.cursor.buffer.borrow().closed
+    }
+
+    pub fn position(&self) -> usize {
+        self.cursor.position()
+    }
+
+    pub fn parse<R>(&mut self, parser : impl FnOnce(&mut PartialCursor<T>) -> Result<R, ParseError>) -> Result<R, ParseError> {
+        self.cursor.buffer.borrow_mut().wanted = 0;
+
+        let mut input = self.cursor.clone();
+        let result = parser(&mut input);
+
+        let (wanted, available) = {
+            let buffer = self.cursor.buffer.borrow();
+            (buffer.wanted, buffer.offset + buffer.items.len())
+        };
+
+        match result {
+            _ if wanted > available => Err(ParseError::Incomplete { needed : wanted - available }),
+            Ok(x) => {
+                self.cursor.index = input.index;
+                drop(input);
+                self.discard();
+                Ok(x)
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    fn discard(&mut self) {
+        if Rc::strong_count(&self.cursor.buffer) == 1 {
+            let mut buffer = self.cursor.buffer.borrow_mut();
+            let consumed = self.cursor.index - buffer.offset;
+            buffer.items.drain(..consumed);
+            buffer.offset = self.cursor.index;
+        }
+    }
+}
+
+impl<T> Default for Partial<T> {
+    fn default() -> Self {
+        Partial::new()
+    }
+}
+
+impl<T> Extend<T> for Partial<T> {
+    fn extend<X : IntoIterator<Item = T>>(&mut self, items : X) {
+        let mut buffer = self.cursor.buffer.borrow_mut();
+        assert!(!buffer.closed, "cannot push onto a closed partial input");
+        buffer.items.extend(items);
+    }
+}
+
+impl<T> PartialCursor<T> {
+    pub fn position(&self) -> usize {
+        self.index
+    }
+}
+
+impl<T> Clone for PartialCursor<T> {
+    fn clone(&self) -> Self {
+        PartialCursor { buffer : Rc::clone(&self.buffer), index : self.index }
+    }
+}
+
+impl<T : Clone> Input for PartialCursor<T> {
+    fn at(&self) -> At {
+        At::Offset(self.position())
+    }
+}
+
+impl<T : Clone> Iterator for PartialCursor<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        let mut buffer = self.buffer.borrow_mut();
+        match buffer.items.get(self.index - buffer.offset) {
+            Some(item) => {
+                let item = item.clone();
+                self.index += 1;
+                Some(item)
+            },
+            None => {
+                if !buffer.closed {
+                    buffer.wanted = buffer.wanted.max(self.index + 1);
+                }
+                None
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::*;
+
+    fn digit(input : &mut impl Iterator<Item = char>) -> Result<u32, ParseError> {
+        match input.next() {
+            Some(c) if c.is_ascii_digit() => Ok(c as u32 - '0' as u32),
+            _ => Err(ParseError::Error),
+        }
+    }
+
+    fn digits(input : &mut (impl Iterator<Item = char> + Clone)) -> Result<Vec<u32>, ParseError> {
+        parser!(input => {
+            ds <= * digit;
+            select ds
+        })
+    }
+
+    fn digits_to_end(input : &mut (impl Iterator<Item = char> + Clone)) -> Result<Vec<u32>, ParseError> {
+        parser!(input => {
+            ds <= * digit;
+            end;
+            select ds
+        })
+    }
+
+    #[test]
+    fn partial_should_report_incomplete_when_parser_reaches_end_of_buffer() {
+        let mut input = Partial::new();
+        input.extend("12".chars());
+
+        let output = input.parse(digits);
+
+        assert!( matches!(output, Err(ParseError::Incomplete { needed : 1 })) );
+    }
+
+    #[test]
+    fn partial_should_parse_after_more_input_is_pushed() {
+        let mut input = Partial::new();
+        input.extend("12".chars());
+
+        assert!( matches!(input.parse(digits), Err(ParseError::Incomplete { .. })) );
+
+        input.extend("3".chars());
+        input.close();
+
+        let output = input.parse(digits).expect("the parse should be successful");
+
+        assert_eq!(output, [1, 2, 3]);
+    }
+
+    #[test]
+    fn partial_should_not_need_more_input_when_parser_stops_early() {
+        let mut input = Partial::new();
+        input.extend("12x".chars());
+
+        let output = input.parse(digits).expect("the parse should be successful");
+
+        assert_eq!(output, [1, 2]);
+        assert_eq!(input.parse(|input| Ok(input.next())).expect("the parse should be successful"), Some('x'));
+    }
+
+    #[test]
+    fn partial_should_not_consume_input_on_incomplete() {
+        let mut input = Partial::new();
+        input.extend("1".chars());
+
+        let _ = input.parse(digits);
+
+        assert_eq!(input.parse(|input| Ok(input.next())).expect("the parse should be successful"), Some('1'));
+    }
+
+    #[test]
+    fn partial_should_only_pass_end_once_closed() {
+        let mut input = Partial::new();
+        input.extend("12".chars());
+
+        assert!( matches!(input.parse(digits_to_end), Err(ParseError::Incomplete { .. })) );
+
+        input.close();
+
+        let output = input.parse(digits_to_end).expect("the parse should be successful");
+
+        assert_eq!(output, [1, 2]);
+    }
+
+    #[test]
+    fn partial_should_pass_through_error() {
+        let mut input = Partial::new();
+        input.extend("x".chars());
+
+        let output = input.parse(|input| parser!(input => {
+            d <= digit;
+            select d
+        }));
+
+        assert!( matches!(output, Err(ParseError::Error)) );
+    }
+}
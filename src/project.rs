@@ -0,0 +1,121 @@
+
+use crate::Span;
+
+// Lets `pat!` rules written against `impl Project<T>` accept owned items, borrowed
+// items, and items paired with a span or an index, without a rule per shape.
+pub trait Project<T> {
+    fn project(&self) -> &T;
+}
+
+impl<T> Project<T> for T {
+    fn project(&self) -> &T {
+        self
+    }
+}
+
+impl<T> Project<T> for &T {
+    fn project(&self) -> &T {
+        self
+    }
+}
+
+impl<T> Project<T> for (Span, T) {
+    fn project(&self) -> &T {
+        &self.1
+    }
+}
+
+impl<T> Project<T> for (Span, &T) {
+    fn project(&self) -> &T {
+        self.1
+    }
+}
+
+impl<T> Project<T> for &(Span, T) {
+    fn project(&self) -> &T {
+        &self.1
+    }
+}
+
+impl<T> Project<T> for (usize, T) {
+    fn project(&self) -> &T {
+        &self.1
+    }
+}
+
+impl<T> Project<T> for (usize, &T) {
+    fn project(&self) -> &T {
+        self.1
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::*;
+
+    #[derive(Debug, Clone, PartialEq)]
+    enum Token {
+        Num(u32),
+        Plus,
+    }
+
+    pat!(num : impl Project<Token> => u32 = Token::Num(n) => *n);
+    pat!(plus : impl Project<Token> => () = ! Token::Plus => ());
+
+    fn sum(input : &mut (impl input::Input<Item = impl Project<Token>> + Clone)) -> Result<u32, ParseError> {
+        parser!(input => {
+            a <= num;
+            _p <= plus;
+            b <= num;
+            end;
+            select a + b
+        })
+    }
+
+    fn tokens() -> Vec<Token> {
+        vec![Token::Num(1), Token::Plus, Token::Num(2)]
+    }
+
+    #[test]
+    fn project_pat_should_parse_owned_items() {
+        let output = sum(&mut tokens().into_iter()).expect("the parse should be successful");
+
+        assert_eq!(output, 3);
+    }
+
+    #[test]
+    fn project_pat_should_parse_borrowed_items() {
+        let tokens = tokens();
+
+        let output = sum(&mut tokens.iter()).expect("the parse should be successful");
+
+        assert_eq!(output, 3);
+    }
+
+    #[test]
+    fn project_pat_should_parse_spanned_and_enumerated_items() {
+        let tokens = tokens();
+        let spanned = tokens.iter().cloned().enumerate().map(|(i, t)| (Span { start : i, end : i + 1 }, t)).collect::<Vec<_>>();
+
+        assert_eq!(sum(&mut spanned.clone().into_iter()).expect("the parse should be successful"), 3);
+        assert_eq!(sum(&mut spanned.iter()).expect("the parse should be successful"), 3);
+        assert_eq!(sum(&mut tokens.iter().enumerate()).expect("the parse should be successful"), 3);
+        assert_eq!(sum(&mut tokens.into_iter().enumerate()).expect("the parse should be successful"), 3);
+    }
+
+    #[test]
+    fn fatal_project_pat_should_fatal() {
+        let tokens = [Token::Num(1), Token::Num(2)];
+        let mut input = tokens.iter();
+
+        let output = sum(&mut input);
+
+        if let Err(ParseError::Fatal(reasons)) = output {
+            assert_eq!(reasons, [Reason::Rule("plus"), Reason::Rule("_p")]);
+        }
+        else {
+            panic!();
+        }
+    }
+}
@@ -0,0 +1,128 @@
+
+use std::cell::{Cell, OnceCell};
+use std::rc::Rc;
+
+use crate::ParseError;
+
+pub type Recursive<'a, I, T> = Rc<dyn Fn(&mut I) -> Result<T, ParseError> + 'a>;
+
+// The handle given to `build` only holds a weak reference, so the finished parser
+// does not keep itself alive.  Calling the handle after the returned parser has
+// been dropped panics.
+pub fn recursive<'a, I : 'a, T : 'a, P>(build : impl FnOnce(Recursive<'a, I, T>) -> P) -> Recursive<'a, I, T>
+    where P : Fn(&mut I) -> Result<T, ParseError> + 'a
+{
+    let cell : Rc<OnceCell<P>> = Rc::new(OnceCell::new());
+
+    let weak = Rc::downgrade(&cell);
+    let handle : Recursive<'a, I, T> = Rc::new(move |input : &mut I| {
+        let cell = weak.upgrade().expect("recursive parser used after it was dropped");
+        let parser = cell.get().expect("recursive parser used before it was built");
+        parser(input)
+    });
+
+    let parser = build(handle);
+    if cell.set(parser).is_err() {
+        unreachable!("recursive parser is only built once");
+    }
+
+    Rc::new(move |input : &mut I| (cell.get().expect("recursive parser was built above"))(input))
+}
+
+pub fn lazy<'a, I : 'a, T : 'a, P>(build : impl FnOnce() -> P + 'a) -> Recursive<'a, I, T>
+    where P : Fn(&mut I) -> Result<T, ParseError> + 'a
+{
+    let build = Cell::new(Some(build));
+    let parser = OnceCell::new();
+
+    Rc::new(move |input : &mut I| {
+        let parser = parser.get_or_init(|| (build.take().expect("lazy parser is only built once"))());
+        parser(input)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::*;
+    use std::str::Chars;
+
+    fn expr<'a>() -> Recursive<'a, Chars<'a>, i64> {
+        recursive(|expr| move |input : &mut Chars<'a>| {
+            alt!(input => number::integer::<i64>; |input : &mut Chars<'a>| parser!(input => {
+                _o <= text::literal("(");
+                a <= ! expr;
+                _p <= ! text::literal("+");
+                b <= ! expr;
+                _c <= ! text::literal(")");
+                select a + b
+            }))
+        })
+    }
+
+    #[test]
+    fn recursive_should_parse_nested_input() {
+        let input = "(1+(2+(3+4)))";
+        let mut input = input.chars();
+
+        let expr = expr();
+        let output = parser!(input => {
+            e <= expr;
+            end;
+            select e
+        }).expect("the parse should be successful");
+
+        assert_eq!(output, 10);
+    }
+
+    #[test]
+    fn recursive_should_keep_fatal_trace() {
+        let input = "(1+(2+x))";
+        let mut input = input.chars();
+
+        let output = expr()(&mut input);
+
+        if let Err(ParseError::Fatal(reasons)) = output {
+            assert_eq!(reasons, [Reason::Rule("b"), Reason::Alt, Reason::Rule("b"), Reason::Alt]);
+        }
+        else {
+            panic!();
+        }
+    }
+
+    #[test]
+    fn recursive_should_be_storable_in_struct() {
+        struct Grammar<'a> {
+            expr : Recursive<'a, Chars<'a>, i64>,
+        }
+
+        let grammar = Grammar { expr : expr() };
+
+        let output = (grammar.expr)(&mut "(5+6)".chars()).expect("the parse should be successful");
+
+        assert_eq!(output, 11);
+    }
+
+    #[test]
+    fn lazy_should_build_on_first_use() {
+        let built = Cell::new(0);
+        let p = lazy(|| {
+            built.set(built.get() + 1);
+            text::digit
+        });
+
+        assert_eq!(built.get(), 0);
+
+        let input = "12";
+        let mut input = input.chars();
+
+        let output = parser!(input => {
+            a <= p;
+            b <= p;
+            select (a, b)
+        }).expect("the parse should be successful");
+
+        assert_eq!(output, ('1', '2'));
+        assert_eq!(built.get(), 1);
+    }
+}
@@ -0,0 +1,316 @@
+
+use std::borrow::Cow;
+use std::str::Chars;
+
+use crate::{ParseError, Reason};
+use crate::text::next_if;
+
+#[derive(Debug, Clone, Copy)]
+pub struct StringLiteral<'q> {
+    pub quotes : &'q [char],
+    pub raw : bool,
+}
+
+impl StringLiteral<'static> {
+    pub const fn new() -> Self {
+        StringLiteral { quotes : &['"'], raw : false }
+    }
+}
+
+impl Default for StringLiteral<'static> {
+    fn default() -> Self {
+        StringLiteral::new()
+    }
+}
+
+impl<'q> StringLiteral<'q> {
+    pub fn parse(&self, input : &mut (impl Iterator<Item = char> + Clone)) -> Result<String, ParseError> {
+        self.literal(input, None).map(Cow::into_owned)
+    }
+
+    pub fn parse_str<'a>(&self, input : &mut Chars<'a>) -> Result<Cow<'a, str>, ParseError> {
+        let source = input.as_str();
+        self.literal(input, Some(source))
+    }
+
+    // The input is left on the offending backslash for a bad escape and on the
+    // opening quote for an unterminated literal.
+    fn literal<'a>(&self, input : &mut (impl Iterator<Item = char> + Clone), source : Option<&'a str>) -> Result<Cow<'a, str>, ParseError> {
+        let start = input.clone();
+
+        let hashes = if self.raw { raw_prefix(input) } else { None };
+        let quote = match input.next() {
+            Some(q) if self.quotes.contains(&q) => q,
+            _ => return Err(ParseError::Error),
+        };
+        let prefix = hashes.map_or(0, |h| 1 + h) + quote.len_utf8();
+
+        let mut owned = match source {
+            Some(_) => None,
+            None => Some(String::new()),
+        };
+        let mut len = 0;
+
+        loop {
+            let at = input.clone();
+            let c = match input.next() {
+                Some(c) => c,
+                None => {
+                    *input = start;
+                    return Err(ParseError::Fatal(vec![Reason::Unterminated]));
+                },
+            };
+
+            if c == quote && closes(input, hashes) {
+                break;
+            }
+
+            let c = if c == '\\' && hashes.is_none() {
+                match escape(input, self.quotes) {
+                    Some(e) => {
+                        if owned.is_none() {
+                            owned = source.map(|s| s[prefix..prefix + len].to_string());
+                        }
+                        e
+                    },
+                    None => {
+                        *input = at;
+                        return Err(ParseError::Fatal(vec![Reason::Escape]));
+                    },
+                }
+            }
+            else {
+                c
+            };
+
+            match owned.as_mut() {
+                Some(s) => s.push(c),
+                None => len += c.len_utf8(),
+            }
+        }
+
+        match (owned, source) {
+            (Some(s), _) => Ok(Cow::Owned(s)),
+            (None, Some(s)) => Ok(Cow::Borrowed(&s[prefix..prefix + len])),
+            (None, None) => unreachable!("literals without a source are always owned"),
+        }
+    }
+}
+
+fn raw_prefix(input : &mut (impl Iterator<Item = char> + Clone)) -> Option<usize> {
+    let mut peek = input.clone();
+    if peek.next() != Some('r') {
+        return None;
+    }
+    let mut hashes = 0;
+    while next_if(&mut peek, |c| *c == '#').is_some() {
+        hashes += 1;
+    }
+    *input = peek;
+    Some(hashes)
+}
+
+fn closes(input : &mut (impl Iterator<Item = char> + Clone), hashes : Option<usize>) -> bool {
+    let mut peek = input.clone();
+    for _ in 0..hashes.unwrap_or(0) {
+        if peek.next() != Some('#') {
+            return false;
+        }
+    }
+    *input = peek;
+    true
+}
+
+fn escape(input : &mut (impl Iterator<Item = char> + Clone), quotes : &[char]) -> Option<char> {
+    match input.next()? {
+        'n' => Some('\n'),
+        't' => Some('\t'),
+        'r' => Some('\r'),
+        '0' => Some('\0'),
+        '\\' => Some('\\'),
+        'u' => unicode(input),
+        c if quotes.contains(&c) || c == '"' || c == '\'' => Some(c),
+        _ => None,
+    }
+}
+
+fn hex(input : &mut impl Iterator<Item = char>, count : usize) -> Option<u32> {
+    let mut ret = 0;
+    for _ in 0..count {
+        ret = ret * 16 + input.next()?.to_digit(16)?;
+    }
+    Some(ret)
+}
+
+fn unicode(input : &mut (impl Iterator<Item = char> + Clone)) -> Option<char> {
+    if next_if(input, |c| *c == '{').is_some() {
+        let mut ret = 0u32;
+        let mut count = 0;
+        while let Some(d) = next_if(input, |c| c.is_ascii_hexdigit()) {
+            ret = ret * 16 + d.to_digit(16)?;
+            count += 1;
+            if count > 6 {
+                return None;
+            }
+        }
+        if count == 0 || input.next()? != '}' {
+            return None;
+        }
+        return char::from_u32(ret);
+    }
+
+    let high = hex(input, 4)?;
+    match high {
+        0xD800..=0xDBFF => {
+            if input.next()? != '\\' || input.next()? != 'u' {
+                return None;
+            }
+            let low = hex(input, 4)?;
+            if !(0xDC00..=0xDFFF).contains(&low) {
+                return None;
+            }
+            char::from_u32(0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00))
+        },
+        _ => char::from_u32(high),
+    }
+}
+
+pub fn string<'a>(input : &mut Chars<'a>) -> Result<Cow<'a, str>, ParseError> {
+    StringLiteral::new().parse_str(input)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::*;
+
+    #[test]
+    fn string_should_borrow_without_escapes() {
+        let input = r#""hello wörld" rest"#;
+        let mut input = input.chars();
+
+        let output = string(&mut input).expect("the parse should be successful");
+
+        assert!( matches!(output, Cow::Borrowed("hello wörld")) );
+        assert_eq!( input.as_str(), " rest" );
+    }
+
+    #[test]
+    fn string_should_decode_escapes() {
+        let input = r#""a\n\t\\\"\u{1F600}é😀""#;
+        let mut input = input.chars();
+
+        let output = string(&mut input).expect("the parse should be successful");
+
+        assert!( matches!(output, Cow::Owned(_)) );
+        assert_eq!( output, "a\n\t\\\"\u{1F600}é\u{1F600}" );
+    }
+
+    #[test]
+    fn string_should_be_usable_in_parser() {
+        let input = r#""x""y""#;
+        let mut input = input.chars();
+
+        let output = parser!(input => {
+            a <= string;
+            b <= string;
+            end;
+            select (a, b)
+        }).expect("the parse should be successful");
+
+        assert_eq!(output, ("x".into(), "y".into()));
+    }
+
+    #[test]
+    fn string_should_fail_without_quote() {
+        let input = "x";
+        let mut input = input.chars();
+
+        let output = string(&mut input);
+
+        assert!( matches!(output, Err(ParseError::Error)) );
+    }
+
+    #[test]
+    fn string_should_fatal_at_bad_escape() {
+        let input = r#""ab\qc""#;
+        let mut input = input.chars();
+
+        let output = string(&mut input);
+
+        if let Err(ParseError::Fatal(reasons)) = output {
+            assert_eq!(reasons, [Reason::Escape]);
+            assert_eq!(input.as_str(), r#"\qc""#);
+        }
+        else {
+            panic!();
+        }
+    }
+
+    #[test]
+    fn string_should_fatal_at_lone_surrogate() {
+        let input = r#""\uD83Dx""#;
+        let mut input = input.chars();
+
+        let output = string(&mut input);
+
+        assert!( matches!(output, Err(ParseError::Fatal(_))) );
+        assert_eq!( input.as_str(), r#"\uD83Dx""# );
+    }
+
+    #[test]
+    fn string_should_fatal_at_opening_quote_when_unterminated() {
+        let input = r#"x "abc"#;
+        let mut input = input.chars();
+        input.next();
+        input.next();
+
+        let output = string(&mut input);
+
+        if let Err(ParseError::Fatal(reasons)) = output {
+            assert_eq!(reasons, [Reason::Unterminated]);
+            assert_eq!(input.as_str(), r#""abc"#);
+        }
+        else {
+            panic!();
+        }
+    }
+
+    #[test]
+    fn string_literal_should_use_configured_quotes() {
+        let config = StringLiteral { quotes : &['\'', '"'], raw : false };
+        let input = r#"'it\'s "fine"'"#;
+        let mut input = input.chars();
+
+        let output = config.parse_str(&mut input).expect("the parse should be successful");
+
+        assert_eq!(output, r#"it's "fine""#);
+    }
+
+    #[test]
+    fn string_literal_should_parse_raw_strings() {
+        let config = StringLiteral { raw : true, ..StringLiteral::new() };
+        let input = r###"r#"a "quoted" \n"# r"b""###;
+        let mut input = input.chars();
+
+        let output = parser!(input => {
+            a <= |i| config.parse_str(i);
+            _w <= text::ws1;
+            b <= |i| config.parse_str(i);
+            select (a, b)
+        }).expect("the parse should be successful");
+
+        assert!( matches!(output.0, Cow::Borrowed(r#"a "quoted" \n"#)) );
+        assert_eq!(output.1, "b");
+    }
+
+    #[test]
+    fn string_literal_should_parse_generic_input() {
+        let input = r#""a\tb""#;
+        let mut input = input.chars().collect::<Vec<_>>().into_iter();
+
+        let output = StringLiteral::new().parse(&mut input).expect("the parse should be successful");
+
+        assert_eq!(output, "a\tb");
+    }
+}
@@ -0,0 +1,333 @@
+
+use crate::{ParseError, Reason};
+
+pub(crate) fn next_if<I : Iterator + Clone>(input : &mut I, pred : impl FnOnce(&I::Item) -> bool) -> Option<I::Item> {
+    let mut peek = input.clone();
+    match peek.next() {
+        Some(x) if pred(&x) => {
+            *input = peek;
+            Some(x)
+        },
+        _ => None,
+    }
+}
+
+fn char_if(input : &mut impl Iterator<Item = char>, pred : impl FnOnce(char) -> bool) -> Result<char, ParseError> {
+    match input.next() {
+        Some(c) if pred(c) => Ok(c),
+        _ => Err(ParseError::Error),
+    }
+}
+
+pub fn any(input : &mut impl Iterator<Item = char>) -> Result<char, ParseError> {
+    char_if(input, |_| true)
+}
+
+pub fn digit(input : &mut impl Iterator<Item = char>) -> Result<char, ParseError> {
+    char_if(input, |c| c.is_ascii_digit())
+}
+
+pub fn hex_digit(input : &mut impl Iterator<Item = char>) -> Result<char, ParseError> {
+    char_if(input, |c| c.is_ascii_hexdigit())
+}
+
+pub fn alpha(input : &mut impl Iterator<Item = char>) -> Result<char, ParseError> {
+    char_if(input, char::is_alphabetic)
+}
+
+pub fn alnum(input : &mut impl Iterator<Item = char>) -> Result<char, ParseError> {
+    char_if(input, char::is_alphanumeric)
+}
+
+pub fn ws(input : &mut impl Iterator<Item = char>) -> Result<char, ParseError> {
+    char_if(input, char::is_whitespace)
+}
+
+pub fn ws0(input : &mut (impl Iterator<Item = char> + Clone)) -> Result<(), ParseError> {
+    while next_if(input, |c| c.is_whitespace()).is_some() { }
+    Ok(())
+}
+
+pub fn ws1(input : &mut (impl Iterator<Item = char> + Clone)) -> Result<(), ParseError> {
+    ws(input)?;
+    ws0(input)
+}
+
+pub fn xid_start(input : &mut impl Iterator<Item = char>) -> Result<char, ParseError> {
+    char_if(input, unicode_ident::is_xid_start)
+}
+
+pub fn xid_continue(input : &mut impl Iterator<Item = char>) -> Result<char, ParseError> {
+    char_if(input, unicode_ident::is_xid_continue)
+}
+
+pub fn ident(input : &mut (impl Iterator<Item = char> + Clone)) -> Result<String, ParseError> {
+    let mut ret = String::from(char_if(input, |c| c == '_' || unicode_ident::is_xid_start(c))?);
+    while let Some(c) = next_if(input, |c| unicode_ident::is_xid_continue(*c)) {
+        ret.push(c);
+    }
+    if ret == "_" {
+        return Err(ParseError::Error);
+    }
+    Ok(ret)
+}
+
+pub fn literal<'a, I : Iterator<Item = char>>(lit : &'a str) -> impl Fn(&mut I) -> Result<&'a str, ParseError> + 'a {
+    move |input| {
+        for expected in lit.chars() {
+            char_if(input, |c| c == expected)?;
+        }
+        Ok(lit)
+    }
+}
+
+pub fn literal_ignore_case<'a, I : Iterator<Item = char>>(lit : &'a str) -> impl Fn(&mut I) -> Result<String, ParseError> + 'a {
+    move |input| {
+        let mut ret = String::new();
+        for expected in lit.chars() {
+            ret.push(char_if(input, |c| c.to_lowercase().eq(expected.to_lowercase()))?);
+        }
+        Ok(ret)
+    }
+}
+
+pub fn one_of<'a, I : Iterator<Item = char>>(chars : &'a str) -> impl Fn(&mut I) -> Result<char, ParseError> + 'a {
+    move |input| char_if(input, |c| chars.contains(c))
+}
+
+pub fn none_of<'a, I : Iterator<Item = char>>(chars : &'a str) -> impl Fn(&mut I) -> Result<char, ParseError> + 'a {
+    move |input| char_if(input, |c| !chars.contains(c))
+}
+
+fn eat(input : &mut (impl Iterator<Item = char> + Clone), lit : &str) -> bool {
+    let mut peek = input.clone();
+    if lit.chars().all(|expected| peek.next() == Some(expected)) {
+        *input = peek;
+        true
+    }
+    else {
+        false
+    }
+}
+
+pub fn line_comment<'a, I : Iterator<Item = char> + Clone>(prefix : &'a str) -> impl Fn(&mut I) -> Result<(), ParseError> + 'a {
+    move |input| {
+        literal(prefix)(input)?;
+        while next_if(input, |c| *c != '\n').is_some() { }
+        Ok(())
+    }
+}
+
+pub fn block_comment<'a, I : Iterator<Item = char> + Clone>(open : &'a str, close : &'a str) -> impl Fn(&mut I) -> Result<(), ParseError> + 'a {
+    move |input| {
+        let start = input.clone();
+        literal(open)(input)?;
+        let mut depth = 1;
+        while depth > 0 {
+            if eat(input, close) {
+                depth -= 1;
+            }
+            else if eat(input, open) {
+                depth += 1;
+            }
+            else if input.next().is_none() {
+                *input = start;
+                return Err(ParseError::Fatal(vec![Reason::Unterminated]));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::*;
+
+    #[test]
+    fn character_classes_should_parse() {
+        let input = "7fZé9 ";
+        let mut input = input.chars();
+
+        let output = parser!(input => {
+            d <= digit;
+            h <= hex_digit;
+            a <= alpha;
+            u <= alpha;
+            n <= alnum;
+            w <= ws;
+            end;
+            select (d, h, a, u, n, w)
+        }).expect("the parse should be successful");
+
+        assert_eq!(output, ('7', 'f', 'Z', 'é', '9', ' '));
+    }
+
+    #[test]
+    fn character_class_should_fail() {
+        let input = "x";
+        let mut input = input.chars();
+
+        let output = digit(&mut input);
+
+        assert!( matches!(output, Err(ParseError::Error)) );
+    }
+
+    #[test]
+    fn ws0_should_accept_no_whitespace() {
+        let input = "x";
+        let mut input = input.chars();
+
+        let output = parser!(input => {
+            _w <= ws0;
+            x <= any;
+            select x
+        }).expect("the parse should be successful");
+
+        assert_eq!(output, 'x');
+    }
+
+    #[test]
+    fn ws1_should_skip_whitespace() {
+        let input = " \t\n x";
+        let mut input = input.chars();
+
+        let output = parser!(input => {
+            _w <= ws1;
+            x <= any;
+            select x
+        }).expect("the parse should be successful");
+
+        assert_eq!(output, 'x');
+    }
+
+    #[test]
+    fn ws1_should_require_whitespace() {
+        let input = "x";
+        let mut input = input.chars();
+
+        let output = parser!(input => {
+            _w <= ws1;
+            select ()
+        });
+
+        assert!( matches!(output, Err(ParseError::Error)) );
+        assert_eq!( input.next(), Some('x') );
+    }
+
+    #[test]
+    fn literal_should_parse() {
+        let input = "let x";
+        let mut input = input.chars();
+
+        let output = parser!(input => {
+            kw <= literal("let");
+            _w <= ws1;
+            x <= ident;
+            select (kw, x)
+        }).expect("the parse should be successful");
+
+        assert_eq!(output, ("let", "x".to_string()));
+    }
+
+    #[test]
+    fn literal_failure_should_reset_input() {
+        let input = "lex";
+        let mut input = input.chars();
+
+        let output = parser!(input => {
+            kw <= literal("let");
+            select kw
+        });
+
+        assert!( matches!(output, Err(ParseError::Error)) );
+        assert_eq!( input.as_str(), "lex" );
+    }
+
+    #[test]
+    fn literal_ignore_case_should_parse() {
+        let input = "SeLeCt";
+        let mut input = input.chars();
+
+        let output = literal_ignore_case("select")(&mut input).expect("the parse should be successful");
+
+        assert_eq!(output, "SeLeCt");
+    }
+
+    #[test]
+    fn one_of_and_none_of_should_parse() {
+        let input = "*a";
+        let mut input = input.chars();
+
+        let output = parser!(input => {
+            op <= one_of("+-*/");
+            x <= none_of("+-*/");
+            select (op, x)
+        }).expect("the parse should be successful");
+
+        assert_eq!(output, ('*', 'a'));
+    }
+
+    #[test]
+    fn none_of_should_fail() {
+        let input = "+";
+        let mut input = input.chars();
+
+        let output = none_of("+-*/")(&mut input);
+
+        assert!( matches!(output, Err(ParseError::Error)) );
+    }
+
+    #[test]
+    fn ident_should_parse_unicode() {
+        let input = "_größe2 = 1";
+        let mut input = input.chars();
+
+        let output = ident(&mut input).expect("the parse should be successful");
+
+        assert_eq!(output, "_größe2");
+        assert_eq!(input.as_str(), " = 1");
+    }
+
+    #[test]
+    fn ident_should_reject_lone_underscore_and_digit_start() {
+        assert!( matches!(ident(&mut "_ ".chars()), Err(ParseError::Error)) );
+        assert!( matches!(ident(&mut "1a".chars()), Err(ParseError::Error)) );
+    }
+
+    #[test]
+    fn line_comment_should_stop_at_newline() {
+        let input = "// note\nx";
+        let mut input = input.chars();
+
+        line_comment("//")(&mut input).expect("the parse should be successful");
+
+        assert_eq!(input.as_str(), "\nx");
+    }
+
+    #[test]
+    fn block_comment_should_nest() {
+        let input = "/* a /* b */ c */x";
+        let mut input = input.chars();
+
+        block_comment("/*", "*/")(&mut input).expect("the parse should be successful");
+
+        assert_eq!(input.as_str(), "x");
+    }
+
+    #[test]
+    fn block_comment_should_fatal_at_opening_when_unterminated() {
+        let input = "/* a /* b */";
+        let mut input = input.chars();
+
+        let output = block_comment("/*", "*/")(&mut input);
+
+        if let Err(ParseError::Fatal(reasons)) = output {
+            assert_eq!(reasons, [Reason::Unterminated]);
+            assert_eq!(input.as_str(), "/* a /* b */");
+        }
+        else {
+            panic!();
+        }
+    }
+}
@@ -0,0 +1,245 @@
+
+use crate::input::Input;
+
+// With the `trace` feature every parser generated by the macros announces itself
+// on entry and reports its outcome on exit, written out as an indented call tree:
+// to `log` or `tracing` when those features are enabled, otherwise to stderr.
+// Without the feature the macros expand exactly as if this module did not exist.
+//
+// Positions are byte offsets (item offsets for `PartialCursor`) taken from
+// `Input::at`.  Inputs that only know how much they have left are shown relative
+// to where the outermost traced parser started.  Without specialization this only
+// works where the input is known to implement `Input`: the functions generated by
+// `pat!` and `rule!` require it, while a hand written generic function has to ask
+// for `impl Input` instead of `impl Iterator` to show more than `?`.
+//
+// A parser left early (a `return`, or a `break` to a label outside of it) still
+// restores the indentation but writes no exit line.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum At {
+    Offset(usize),
+    Remaining(usize),
+    Unknown,
+}
+
+// The macros do not know what their input is.  Calling
+// `(&&Position(input)).at()` uses `Input::at` when the input implements it.
+pub struct Position<'a, I>(pub &'a I);
+
+pub trait Located {
+    fn at(&self) -> At;
+}
+
+pub trait Unknown {
+    fn at(&self) -> At;
+}
+
+impl<I : Input> Located for &Position<'_, I> {
+    fn at(&self) -> At {
+        self.0.at()
+    }
+}
+
+impl<I> Unknown for Position<'_, I> {
+    fn at(&self) -> At {
+        At::Unknown
+    }
+}
+
+#[cfg(feature = "trace")]
+pub use imp::{capture, enter, rule, Scope};
+
+#[cfg(feature = "trace")]
+mod imp {
+    use std::cell::{Cell, RefCell};
+
+    use crate::ParseError;
+    use super::At;
+
+    thread_local! {
+        static DEPTH : Cell<usize> = const { Cell::new(0) };
+        // What the outermost parser had left, so that inputs which only know how
+        // much is remaining can still be shown as an offset.
+        static ROOT : Cell<Option<usize>> = const { Cell::new(None) };
+        static CAPTURED : RefCell<Option<Vec<String>>> = const { RefCell::new(None) };
+    }
+
+    // Collects the lines written while `f` runs instead of emitting them.
+    pub fn capture<R>(f : impl FnOnce() -> R) -> (R, Vec<String>) {
+        let outer = CAPTURED.with(|c| c.replace(Some(vec![])));
+        let ret = f();
+        let lines = CAPTURED.with(|c| c.replace(outer)).unwrap_or_default();
+        (ret, lines)
+    }
+
+    pub struct Scope {
+        name : &'static str,
+        depth : usize,
+    }
+
+    pub fn enter(name : &'static str, at : At) -> Scope {
+        let depth = DEPTH.with(Cell::get);
+        if depth == 0 {
+            ROOT.with(|r| r.set(match at { At::Remaining(n) => Some(n), _ => None }));
+        }
+        let offset = match at {
+            At::Offset(n) => Some(n),
+            At::Remaining(n) => ROOT.with(Cell::get).map(|root| root.saturating_sub(n)),
+            At::Unknown => None,
+        };
+        match offset {
+            Some(offset) => emit(depth, format!("{} (at {})", name, offset)),
+            None => emit(depth, format!("{} (at ?)", name)),
+        }
+        DEPTH.with(|d| d.set(depth + 1));
+        Scope { name, depth }
+    }
+
+    impl Scope {
+        pub fn exit<T>(self, result : &Result<T, ParseError>) {
+            let outcome = match result {
+                Ok(_) => "Ok".to_string(),
+                Err(ParseError::Error) => "Error".to_string(),
+                Err(ParseError::Fatal(reasons)) => format!("Fatal: {}", reasons.iter().map(|r| r.to_string()).collect::<Vec<_>>().join(", ")),
+                Err(ParseError::Incomplete { needed }) => format!("Incomplete: {} more needed", needed),
+            };
+            emit(self.depth, format!("{} => {}", self.name, outcome));
+        }
+    }
+
+    impl Drop for Scope {
+        fn drop(&mut self) {
+            DEPTH.with(|d| d.set(self.depth));
+        }
+    }
+
+    pub fn rule<I, T>(name : &'static str, at : At, input : &mut I, parser : impl FnOnce(&mut I) -> Result<T, ParseError>) -> Result<T, ParseError> {
+        let scope = enter(name, at);
+        let ret = parser(input);
+        scope.exit(&ret);
+        ret
+    }
+
+    fn emit(depth : usize, line : String) {
+        let line = format!("{}{}", "  ".repeat(depth), line);
+        let line = CAPTURED.with(|c| match c.borrow_mut().as_mut() {
+            Some(lines) => {
+                lines.push(line);
+                None
+            },
+            None => Some(line),
+        });
+        let Some(line) = line else { return; };
+
+        #[cfg(feature = "log")]
+        log::trace!("{}", line);
+        #[cfg(feature = "tracing")]
+        tracing::trace!("{}", line);
+        #[cfg(not(any(feature = "log", feature = "tracing")))]
+        eprintln!("{}", line);
+    }
+}
+
+#[cfg(all(test, feature = "trace"))]
+mod test {
+    use super::*;
+    use crate::*;
+    use std::str::Chars;
+
+    pat!(open<'a> : char => () = '(' => ());
+    pat!(close<'a> : char => () = ! ')' => ());
+
+    fn parens(input : &mut Chars) -> Result<usize, ParseError> {
+        alt!(input => |input : &mut Chars| parser!(input => {
+            _o <= open;
+            n <= parens;
+            _c <= close;
+            select n + 1
+        }); |_ : &mut Chars| Ok(0))
+    }
+
+    // Drops source locations, leaving names, positions, nesting and outcomes.
+    fn names(lines : &[String]) -> Vec<String> {
+        lines.iter().map(|line| {
+            match line.split_once(" at ") {
+                Some((name, rest)) => format!("{}{}", name, &rest[rest.find(' ').unwrap()..]),
+                None => line.clone(),
+            }
+        }).collect()
+    }
+
+    #[test]
+    fn trace_should_render_call_tree() {
+        let (output, lines) = capture(|| parens(&mut "()".chars()));
+
+        assert_eq!(output.expect("the parse should be successful"), 1);
+        assert!( lines[0].starts_with(concat!("alt! at ", file!(), ":")) );
+        assert_eq!(names(&lines), [
+            "alt! (at 0)",
+            "  parser! (at 0)",
+            "    open (at 0)",
+            "    open => Ok",
+            "    alt! (at 1)",
+            "      parser! (at 1)",
+            "        open (at 1)",
+            "        open => Error",
+            "      parser! => Error",
+            "    alt! => Ok",
+            "    close (at 1)",
+            "    close => Ok",
+            "  parser! => Ok",
+            "alt! => Ok",
+        ]);
+    }
+
+    #[test]
+    fn trace_should_report_fatal_reasons() {
+        let (output, lines) = capture(|| parens(&mut "(".chars()));
+
+        assert!( matches!(output, Err(ParseError::Fatal(_))) );
+        assert!( lines.last().unwrap().ends_with(" => Fatal: Rule: close, Rule: _c, Alternative") );
+    }
+
+    rule!(pair : char => (char, char) = {
+        a <= text::any;
+        b <= text::any;
+        select (a, b)
+    });
+
+    #[test]
+    fn trace_should_use_rule_names() {
+        let (output, lines) = capture(|| pair(&mut "xy".chars()));
+
+        assert_eq!(output.expect("the parse should be successful"), ('x', 'y'));
+        assert_eq!(lines, ["pair (at 0)", "pair => Ok"]);
+    }
+
+    fn digits(input : &mut cst::Cst<Chars>) -> Result<String, ParseError> {
+        node!(input => digits {
+            ds <= * text::digit;
+            select ds.into_iter().collect::<String>()
+        })
+    }
+
+    fn spaced(input : &mut cst::Cst<Chars>) -> Result<String, ParseError> {
+        parser!(input => {
+            _w <= text::ws0;
+            ds <= digits;
+            select ds
+        })
+    }
+
+    #[test]
+    fn trace_should_show_offsets_of_located_inputs() {
+        let (output, lines) = capture(|| spaced(&mut cst::Cst::new("  12".chars())));
+
+        assert_eq!(output.expect("the parse should be successful"), "12");
+        assert_eq!(names(&lines), [
+            "parser! (at 0)",
+            "  digits (at 2)",
+            "  digits => Ok",
+            "parser! => Ok",
+        ]);
+    }
+}